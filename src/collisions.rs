@@ -2,6 +2,8 @@
 //use ndarray::prelude::*;
 
 use crate::particle;
+use crate::parameters;
+use crate::parallel;
 use crate::simulation::status_bar;
 
 use std::collections::BinaryHeap;
@@ -21,6 +23,21 @@ pub struct Collision
                             // with the particles' actual collision count.
 }
 
+// What happened in a resolved collision. The relative normal
+// velocity is negative before the collision, when the particles
+// (or the particle and the wall) approach each other.
+#[derive(Clone, Debug, PartialEq)]
+pub struct CollisionOutcome
+{
+    pub time: f64,
+    pub particle_1: i32,
+    pub particle_2: i32,     // Negative for walls, like in Collision
+    pub normal_velocity_before: f64,
+    pub normal_velocity_after: f64,
+    pub impulse: f64,        // Magnitude of the momentum given to particle_1
+    pub energy_loss: f64,    // Kinetic energy dissipated
+}
+
 impl Collision
 {
     pub fn get_time(&self) -> f64
@@ -66,8 +83,9 @@ of particles in a collision"),
 
 
 
-    // Transform the velocities of the particles involved in the collision.
-    pub fn transform_velocity(&self, p: &mut particle::Particles, xi: f64)
+    // Transform the velocities of the particles involved in the collision,
+    // and return what happened.
+    pub fn transform_velocity(&self, p: &mut particle::Particles, xi: f64) -> CollisionOutcome
     {
         // particle_2 is either a particle or a wall.
         // a positive index means particle, a negative means wall
@@ -76,10 +94,15 @@ of particles in a collision"),
         let p_1 = self.get_particle_1() as usize;
         let p_2 = self.get_particle_2();
 
+        let v_1 = [p.vel[[0, p_1]], p.vel[[1, p_1]]];
+        let mut energy_before = p.get_kinetic_energy(p_1);
+        let normal_velocity_before;
+
         // Collide with horizontal wall
         if p_2 == -1
         {
             //println!("Horizontal wall transform complete");
+            normal_velocity_before = -p.vel[[1, p_1]].abs();
             p.vel[[0, p_1]] *= xi;
             p.vel[[1, p_1]] *= - xi;
         }
@@ -87,15 +110,18 @@ of particles in a collision"),
         else if p_2 == -2
         {
             //println!("Vertical wall transform complete");
+            normal_velocity_before = -p.vel[[0, p_1]].abs();
             p.vel[[0, p_1]] *= - xi;
             p.vel[[1, p_1]] *= xi;
         }
         // Collide with a particle.
         else
         {
-            let (r_2, _d, dvdx, _dx_2, _dv_2, dx) 
+            let (r_2, _d, dvdx, dx_2, _dv_2, dx) 
                 = particle::calculate_impact_stats(
                     &p.pos, &p.vel, &p.r, p_1, p_2 as usize);
+            normal_velocity_before = dvdx / dx_2.sqrt();
+            energy_before += p.get_kinetic_energy(p_2 as usize);
 
             let mu_1 = p.m[p_2 as usize] 
                 / (p.m[p_1] + p.m[p_2 as usize]);
@@ -116,6 +142,23 @@ of particles in a collision"),
             p.increment_collision_count(p_2 as usize);
         }
         p.increment_collision_count(p_1);
+
+        let mut energy_after = p.get_kinetic_energy(p_1);
+        if p_2 >= 0
+        {
+            energy_after += p.get_kinetic_energy(p_2 as usize);
+        }
+        let dv_1 = [p.vel[[0, p_1]] - v_1[0], p.vel[[1, p_1]] - v_1[1]];
+        CollisionOutcome
+        {
+            time: self.time,
+            particle_1: p_1 as i32,
+            particle_2: p_2,
+            normal_velocity_before,
+            normal_velocity_after: -xi * normal_velocity_before,
+            impulse: p.m[p_1] * (dv_1[0].powi(2) + dv_1[1].powi(2)).sqrt(),
+            energy_loss: energy_before - energy_after,
+        }
     }
 }
 
@@ -133,12 +176,10 @@ impl CollisionQueue
         CollisionQueue { heap: BinaryHeap::new() }
     }
 
-    /*
     pub fn get_len(&self) -> usize
     {
         self.heap.len()
     }
-    */
 
     /*
     pub fn get_next(&self) -> &Collision
@@ -166,22 +207,65 @@ impl CollisionQueue
     {
         &self.heap
     }
+}
+
+
+// A queue of upcoming collisions, earliest first. Entries can
+// become outdated when one of the particles collides with something
+// else first, which Collision::is_valid tells. CollisionQueue keeps
+// every collision that is found, and indexed_queue::IndexedQueue
+// only the earliest one of each particle.
+pub trait EventQueue
+{
+    fn get_len(&self) -> usize;
+
+    fn peek_next(&self) -> Option<&Collision>;
+
+    fn pop_next(&mut self) -> Collision;
+
+    // Removes the next collision, which must be outdated.
+    fn discard_next(&mut self, particles: &particle::Particles, t: f64, x_max: f64, y_max: f64);
+
+    // Adds collisions of particle i, as returned by find_collisions.
+    fn insert_collisions(&mut self, i: usize, collisions: Vec<Collision>);
+
+    // Iterates through the entries, in no particular order.
+    fn iter(&self) -> Box<dyn Iterator<Item = &Collision> + '_>;
+
+    // Adds the upcoming collisions of particle i, found at time t.
+    fn add_new_collisions(
+        &mut self, particles: &particle::Particles, i: usize, t: f64, x_max: f64, y_max: f64)
+    {
+        let collisions = find_collisions(particles, i, t, x_max, y_max);
+        self.insert_collisions(i, collisions);
+    }
 
     // Iterates through all existing particles, and
-    // adds all expected collisions to CollisionQueue.
-    // This will create double entries.
-    pub fn fill_collision_queue(&mut self, particles: &particle::Particles, t_0: f64, x_max: f64, y_max: f64)
+    // adds all expected collisions to the queue.
+    // Blocks of particles are handled on several threads,
+    // and inserted in order, as they would be one by one.
+    fn fill_collision_queue(&mut self, particles: &particle::Particles, t_0: f64, x_max: f64, y_max: f64)
     {
-        for i in 0..particles.get_len()
+        let n = particles.get_len();
+        // Large enough blocks that each thread has plenty to do,
+        // and few enough that the status bar still moves.
+        let block = n.div_ceil(50).max(16*parallel::get_workers());
+        for start in (0..n).step_by(block)
         {
-            status_bar(i, particles.get_len());
-            self.add_new_collisions(particles, i, t_0, x_max, y_max);
+            status_bar(start, n);
+            let found = parallel::map_ordered(block.min(n - start),
+                |k| find_collisions(particles, start + k, t_0, x_max, y_max));
+            for (k, collisions) in found.into_iter().enumerate()
+            {
+                self.insert_collisions(start + k, collisions);
+            }
         }
     }
 
     // This will not create double entries, because
     // particle 1 and 2 cannot crash twice in a row.
-    pub fn resolve_next_collision(
+    // Returns what happened in the collision.
+    fn resolve_next_collision(
         &mut self, 
         c: &Collision, 
         mut particles: &mut particle::Particles, 
@@ -189,9 +273,10 @@ impl CollisionQueue
         xi: f64, 
         x_max: f64, 
         y_max: f64)
+        -> CollisionOutcome
     {
         //let c = self.pop_next();
-        c.transform_velocity(&mut particles, xi);
+        let outcome = c.transform_velocity(&mut particles, xi);
         let p_1 = c.particle_1_index;
         let p_2 = c.particle_2_index;
 
@@ -202,21 +287,67 @@ impl CollisionQueue
         {
             self.add_new_collisions(particles, p_2 as usize, t, x_max, y_max);
         }
+        outcome
+    }
+}
+
+
+impl EventQueue for CollisionQueue
+{
+    fn get_len(&self) -> usize
+    {
+        self.heap.len()
+    }
+
+    fn peek_next(&self) -> Option<&Collision>
+    {
+        self.heap.peek()
     }
 
+    fn pop_next(&mut self) -> Collision
+    {
+        CollisionQueue::pop_next(self)
+    }
 
-    pub fn add_new_collisions(
-        &mut self, particles: &particle::Particles, i: usize, t: f64, x_max: f64, y_max: f64)
+    // Outdated entries are simply dropped, because the
+    // collisions that replace them are already queued.
+    fn discard_next(&mut self, _particles: &particle::Particles, _t: f64, _x_max: f64, _y_max: f64)
     {
-        for j in 0..particles.get_len() + 2
+        self.heap.pop();
+    }
+
+    fn insert_collisions(&mut self, _i: usize, collisions: Vec<Collision>)
+    {
+        for c in collisions
         {
-            let c = find_new_collision(particles, i, j as i32 - 2, t, x_max, y_max);
-            if c.get_time().is_finite()
-            {
-                self.push_collision(c);
-            }
+            self.push_collision(c);
         }
     }
+
+    fn iter(&self) -> Box<dyn Iterator<Item = &Collision> + '_>
+    {
+        Box::new(self.heap.iter())
+    }
+}
+
+
+// Returns the upcoming collisions of particle i with the walls
+// and then every particle, in that order. In large systems, they
+// are found on several threads, which does not change the result.
+pub fn find_collisions(particles: &particle::Particles, i: usize, t: f64, x_max: f64, y_max: f64)
+    -> Vec<Collision>
+{
+    let find = |j: usize| find_new_collision(particles, i, j as i32 - 2, t, x_max, y_max);
+    let n = particles.get_len() + 2;
+    let found: Vec<Collision> = if particles.get_len() >= parameters::PARALLEL_PAIR_THRESHOLD
+    {
+        parallel::map_ordered(n, find)
+    }
+    else
+    {
+        (0..n).map(find).collect()
+    };
+    found.into_iter().filter(|c| c.get_time().is_finite()).collect()
 }
 
 
@@ -227,7 +358,7 @@ pub fn find_new_collision(
     let cc_1 = particles.get_collision_count(i as i32);
     let cc_2 = particles.get_collision_count(j);
 
-    let (dt, n) = particles.time_until_next_collisions(i, j, x_max, y_max);
+    let (dt, n) = particles.time_until_next_collisions(i, j, t, x_max, y_max);
     return make_collision(t + dt, i as usize, n, cc_1, cc_2);
 }
 
@@ -0,0 +1,259 @@
+use ndarray::prelude::*;
+
+use crate::particle;
+use crate::collisions;
+use crate::simulation::status_bar;
+
+
+// Lubachevsky-Stillinger compression: the particles move like in
+// an ordinary elastic simulation, but every radius grows linearly in
+// time, proportionally to its initial value. Collisions are resolved
+// so that the particles separate faster than their surfaces grow.
+// This reaches far higher packing fractions than random placement.
+//
+// growth_rate is the speed at which the surface of the largest
+// particle grows, and should be small compared to the particle
+// speeds. Every particle must be moving or be able to be pushed.
+//
+// The compression stops when the packing fraction reaches target,
+// or after max_events collisions. Returns the packing fraction
+// reached, and whether it is the target. The velocities after
+// compression are rescaled to the initial kinetic energy, and
+// all collision counts are reset.
+pub fn compress(
+    p: &mut particle::Particles,
+    target: f64,
+    growth_rate: f64,
+    max_events: usize,
+    x_max: f64,
+    y_max: f64)
+    -> (f64, bool)
+{
+    assert!(target < 1., "A packing fraction of {} is impossible.", target);
+    assert!(growth_rate > 0.);
+
+    let n = p.get_len();
+    let r_max = p.r.fold(0., |a: f64, r| a.max(*r));
+    let rates = p.r.mapv(|r| growth_rate * r / r_max);
+    let e_0 = p.get_tot_kinetic_energy();
+
+    // The radii grow as r(t) = r(0) * (1 + t * growth_rate / r_max),
+    // so the packing fraction grows quadratically in time.
+    let pf_0 = particle::get_area_fraction(&p.r, 0., 0., x_max, y_max);
+    let t_end = ((target / pf_0).sqrt() - 1.) * r_max / growth_rate;
+    if t_end <= 0.
+    {
+        return (pf_0, true);
+    }
+
+    let mut t = 0.;
+    let mut events: usize = 0;
+    let mut q = fill_growing_queue(p, &rates, t, x_max, y_max);
+
+    println!("Compressing particles from a packing fraction of {:.3} to {:.3}.", pf_0, target);
+    while events < max_events
+    {
+        let c = q.pop_next();
+        if !c.is_valid(p)
+        {
+            continue;
+        }
+        if c.get_time() >= t_end
+        {
+            break;
+        }
+        status_bar(events, max_events);
+
+        let dt = c.get_time() - t;
+        grow(p, &rates, dt);
+        t = c.get_time();
+        events += 1;
+
+        transform_growing_velocity(p, &rates, &c, x_max, y_max);
+        let p_1 = c.get_particle_1() as usize;
+        let p_2 = c.get_particle_2();
+        add_growing_collisions(&mut q, p, &rates, p_1, t, x_max, y_max);
+        if p_2 >= 0
+        {
+            add_growing_collisions(&mut q, p, &rates, p_2 as usize, t, x_max, y_max);
+        }
+
+        // Collisions with growing particles add kinetic energy. Rescale
+        // the velocities now and then, and start over with a new queue,
+        // which also keeps the queue from filling up with stale entries.
+        if events.is_multiple_of(n)
+        {
+            rescale_velocities(p, e_0);
+            q = fill_growing_queue(p, &rates, t, x_max, y_max);
+        }
+    }
+
+    let reached = events < max_events;
+    if reached
+    {
+        grow(p, &rates, t_end - t);
+    }
+    else
+    {
+        println!("\nWARNING: Compression stopped after {} events.", events);
+    }
+    rescale_velocities(p, e_0);
+    p.collision_count = Array1::zeros(n);
+
+    let pf = particle::get_area_fraction(&p.r, 0., 0., x_max, y_max);
+    println!(" Done.");
+    println!("Reached a packing fraction of {:.4} after {} events.", pf, events);
+    (pf, reached)
+}
+
+
+// Moves all particles, and grows their radii, for a time dt.
+fn grow(p: &mut particle::Particles, rates: &Array1<f64>, dt: f64)
+{
+    p.propagate(dt);
+    p.r.scaled_add(dt, rates);
+}
+
+
+fn rescale_velocities(p: &mut particle::Particles, energy: f64)
+{
+    let e = p.get_tot_kinetic_energy();
+    if e > 0.
+    {
+        p.vel *= (energy / e).sqrt();
+    }
+}
+
+
+fn fill_growing_queue(
+    p: &particle::Particles, rates: &Array1<f64>, t: f64, x_max: f64, y_max: f64)
+    -> collisions::CollisionQueue
+{
+    let mut q = collisions::CollisionQueue::new();
+    for i in 0..p.get_len()
+    {
+        add_growing_collisions(&mut q, p, rates, i, t, x_max, y_max);
+    }
+    q
+}
+
+
+// Like CollisionQueue::add_new_collisions, but for growing particles.
+fn add_growing_collisions(
+    q: &mut collisions::CollisionQueue,
+    p: &particle::Particles,
+    rates: &Array1<f64>,
+    i: usize,
+    t: f64,
+    x_max: f64,
+    y_max: f64)
+{
+    let cc_1 = p.get_collision_count(i as i32);
+    let walls = [
+        (-1, growing_wall_time(p.pos[[1, i]], p.vel[[1, i]], p.r[i], rates[i], y_max)),
+        (-2, growing_wall_time(p.pos[[0, i]], p.vel[[0, i]], p.r[i], rates[i], x_max))];
+    for (j, dt) in walls.iter()
+    {
+        if dt.is_finite()
+        {
+            q.push_collision(collisions::make_collision(t + dt, i, *j, cc_1, 0));
+        }
+    }
+
+    for j in 0..p.get_len()
+    {
+        if j == i
+        {
+            continue;
+        }
+        let dt = growing_particle_time(p, rates, i, j);
+        if dt.is_finite()
+        {
+            let cc_2 = p.get_collision_count(j as i32);
+            q.push_collision(collisions::make_collision(t + dt, i, j as i32, cc_1, cc_2));
+        }
+    }
+}
+
+
+// Time until a growing particle hits one of the walls at 0 and length.
+fn growing_wall_time(pos: f64, v: f64, r: f64, rate: f64, length: f64) -> f64
+{
+    let upper = if v + rate > 0. { (length - r - pos) / (v + rate) } else { f64::INFINITY };
+    let lower = if v - rate < 0. { (r - pos) / (v - rate) } else { f64::INFINITY };
+    upper.min(lower).max(0.)
+}
+
+
+// Time until particles i and j touch, when both grow. This is the
+// smallest positive root of |dx + dv t|^2 = (sigma + a t)^2.
+fn growing_particle_time(p: &particle::Particles, rates: &Array1<f64>, i: usize, j: usize)
+    -> f64
+{
+    let dx = [p.pos[[0, j]] - p.pos[[0, i]], p.pos[[1, j]] - p.pos[[1, i]]];
+    let dv = [p.vel[[0, j]] - p.vel[[0, i]], p.vel[[1, j]] - p.vel[[1, i]]];
+    let sigma = p.r[i] + p.r[j];
+    let a = rates[i] + rates[j];
+
+    let q_a = dv[0].powi(2) + dv[1].powi(2) - a.powi(2);
+    let q_b = dx[0]*dv[0] + dx[1]*dv[1] - sigma*a;
+    let q_c = dx[0].powi(2) + dx[1].powi(2) - sigma.powi(2);
+    let d = q_b.powi(2) - q_a*q_c;
+
+    if q_a < 0.
+    {
+        // The gap closes eventually, however the particles move.
+        (-(q_b + d.max(0.).sqrt()) / q_a).max(0.)
+    }
+    else if q_b < 0. && d >= 0.
+    {
+        (q_c / (-q_b + d.sqrt())).max(0.)
+    }
+    else
+    {
+        f64::INFINITY
+    }
+}
+
+
+// Resolves an elastic collision between growing particles. The normal
+// velocity is reflected in the frame of the growing surfaces, so that
+// the particles move apart faster than they grow.
+fn transform_growing_velocity(
+    p: &mut particle::Particles,
+    rates: &Array1<f64>,
+    c: &collisions::Collision,
+    x_max: f64,
+    y_max: f64)
+{
+    let i = c.get_particle_1() as usize;
+    let j = c.get_particle_2();
+
+    if j < 0
+    {
+        // -1 is a horizontal wall, -2 a vertical one.
+        let (axis, length) = if j == -1 { (1, y_max) } else { (0, x_max) };
+        let at_upper = p.pos[[axis, i]] > 0.5 * length;
+        let v = p.vel[[axis, i]];
+        p.vel[[axis, i]] = if at_upper { -v - 2.*rates[i] } else { 2.*rates[i] - v };
+    }
+    else
+    {
+        let j = j as usize;
+        let dx = [p.pos[[0, j]] - p.pos[[0, i]], p.pos[[1, j]] - p.pos[[1, i]]];
+        let dist = (dx[0].powi(2) + dx[1].powi(2)).sqrt();
+        let normal = [dx[0] / dist, dx[1] / dist];
+        let u = (p.vel[[0, j]] - p.vel[[0, i]]) * normal[0]
+            + (p.vel[[1, j]] - p.vel[[1, i]]) * normal[1];
+        let du = 2. * (rates[i] + rates[j] - u);
+
+        let m_tot = p.m[i] + p.m[j];
+        for (k, n_k) in normal.iter().enumerate()
+        {
+            p.vel[[k, i]] -= p.m[j] / m_tot * du * n_k;
+            p.vel[[k, j]] += p.m[i] / m_tot * du * n_k;
+        }
+        p.increment_collision_count(j);
+    }
+    p.increment_collision_count(i);
+}
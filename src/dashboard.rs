@@ -0,0 +1,240 @@
+use std::collections::VecDeque;
+use std::io::{self, Write};
+use std::time::Instant;
+
+use crate::particle;
+use crate::observer::Observer;
+use crate::simulation::Simulation;
+use crate::save_data;
+
+
+// How the density map is drawn. Blocks shade every character
+// by the number of particles in it, Braille shows every
+// occupied cell as a dot, with 2 x 4 dots per character.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum MapStyle
+{
+    Blocks,
+    Braille,
+}
+
+
+const SHADES: [char; 5] = [' ', '░', '▒', '▓', '█'];
+const SPARKS: [char; 8] = ['▁', '▂', '▃', '▄', '▅', '▆', '▇', '█'];
+// Number of refreshes shown in the sparklines.
+const HISTORY: usize = 40;
+
+
+// A live view of a running simulation in the terminal, drawn
+// instead of the status bar. It shows a density map of the box,
+// the energy, a sparkline of the temperature of every species,
+// the number of events per second and the size of the queue.
+// Nothing is computed between refreshes, so it costs little
+// as long as it is refreshed rarely compared to the number
+// of particles.
+pub struct Dashboard
+{
+    every: usize,
+    width: usize,
+    style: MapStyle,
+    energy: VecDeque<f64>,
+    temperatures: Vec<VecDeque<f64>>,
+    e_0: Option<f64>,
+    last_event: usize,
+    last_time: Instant,
+    drawn: bool,
+}
+
+
+impl Dashboard
+{
+    // The map is width characters wide, and the dashboard
+    // is redrawn every `every` events.
+    pub fn new(every: usize, width: usize, style: MapStyle) -> Dashboard
+    {
+        assert!(every > 0 && width > 0);
+        Dashboard
+        {
+            every,
+            width,
+            style,
+            energy: VecDeque::with_capacity(HISTORY),
+            temperatures: Vec::new(),
+            e_0: None,
+            last_event: 0,
+            last_time: Instant::now(),
+            drawn: false,
+        }
+    }
+
+    // Called after every event. Redraws the dashboard if
+    // event is a multiple of the refresh interval.
+    pub fn update(&mut self, p: &particle::Particles, queue_len: usize, event: usize, t: f64,
+        x_max: f64, y_max: f64)
+    {
+        if !event.is_multiple_of(self.every)
+        {
+            return;
+        }
+        let text = self.draw(p, queue_len, event, t, x_max, y_max);
+        // Clear the screen once, and then only move the cursor
+        // back home, so that the dashboard does not flicker.
+        let clear = if self.drawn { "\x1b[H" } else { "\x1b[H\x1b[2J" };
+        self.drawn = true;
+        let mut stdout = io::stdout();
+        // The dashboard is only for show, so failed writes are ignored.
+        write!(stdout, "{}{}", clear, text).ok();
+        stdout.flush().ok();
+    }
+
+    // Samples the state of the system, and returns the dashboard as text.
+    pub fn draw(&mut self, p: &particle::Particles, queue_len: usize, event: usize, t: f64,
+        x_max: f64, y_max: f64)
+        -> String
+    {
+        let now = Instant::now();
+        let seconds = now.duration_since(self.last_time).as_secs_f64();
+        let rate = if seconds > 0. { event.saturating_sub(self.last_event) as f64 / seconds } else { 0. };
+        self.last_event = event;
+        self.last_time = now;
+
+        let energy = p.get_tot_kinetic_energy();
+        let e_0 = *self.e_0.get_or_insert(energy);
+        push_sample(&mut self.energy, energy);
+
+        let n_species = p.get_species_count();
+        self.temperatures.resize(n_species, VecDeque::with_capacity(HISTORY));
+        let mut counts = vec![0; n_species];
+        for k in p.species.iter()
+        {
+            counts[*k] += 1;
+        }
+        for (k, history) in self.temperatures.iter_mut().enumerate()
+        {
+            // With two degrees of freedom, E = N T for every species.
+            let temperature = p.get_kinetic_energy_for_species(k) / counts[k].max(1) as f64;
+            push_sample(history, temperature);
+        }
+
+        let mut text = self.draw_map(p, x_max, y_max);
+        text += &format!("Event {:>10}   t = {:<12.6}   events/s = {:<10.0}   queue = {:<10}\x1b[K\n",
+            event, t, rate, queue_len);
+        text += &format!("Energy {:<12.4e} ({:>6.2} % of initial) {}\x1b[K\n",
+            energy, 100. * energy / e_0, sparkline(&self.energy));
+        let name_width = p.species_names.iter().map(|name| name.chars().count()).max().unwrap_or(0);
+        for (k, history) in self.temperatures.iter().enumerate()
+        {
+            text += &format!("T {:<w$} {:<12.4e} {}\x1b[K\n",
+                p.species_names[k], history.back().unwrap(), sparkline(history), w = name_width);
+        }
+        text
+    }
+
+    // Draws the box, with the density of the particles
+    // from the bottom (y = 0) to the top.
+    fn draw_map(&self, p: &particle::Particles, x_max: f64, y_max: f64) -> String
+    {
+        // Cells of the density grid per character.
+        let (cell_x, cell_y) = match self.style
+        {
+            MapStyle::Blocks => (1, 1),
+            MapStyle::Braille => (2, 4),
+        };
+        // Characters are about twice as high as they are wide.
+        let rows = ((self.width as f64 * y_max / x_max / 2.).round() as usize).max(1);
+        let (nx, ny) = (self.width * cell_x, rows * cell_y);
+
+        let mut grid = vec![0usize; nx * ny];
+        for i in 0..p.get_len()
+        {
+            let column = ((p.pos[[0, i]] / x_max * nx as f64) as usize).min(nx - 1);
+            let row = ((p.pos[[1, i]] / y_max * ny as f64) as usize).min(ny - 1);
+            grid[row * nx + column] += 1;
+        }
+        let mean = p.get_len() as f64 / grid.len() as f64;
+
+        let mut text = format!("+{}+\x1b[K\n", "-".repeat(self.width));
+        for line in (0..rows).rev()
+        {
+            text.push('|');
+            for column in 0..self.width
+            {
+                text.push(match self.style
+                {
+                    MapStyle::Blocks =>
+                    {
+                        let density = grid[line * nx + column] as f64 / mean;
+                        let level = if density == 0. { 0 }
+                            else { ((density * 2.).ceil() as usize).min(SHADES.len() - 1) };
+                        SHADES[level]
+                    }
+                    MapStyle::Braille =>
+                    {
+                        // BITS[dx][dy] is the bit of the braille code that
+                        // sets the dot in column dx, dy rows from the bottom.
+                        const BITS: [[u32; 4]; 2] = [[6, 2, 1, 0], [7, 5, 4, 3]];
+                        let mut code = 0x2800;
+                        for (dx, bits) in BITS.iter().enumerate()
+                        {
+                            for (dy, bit) in bits.iter().enumerate()
+                            {
+                                if grid[(line * 4 + dy) * nx + column * 2 + dx] > 0
+                                {
+                                    code |= 1 << bit;
+                                }
+                            }
+                        }
+                        std::char::from_u32(code).unwrap()
+                    }
+                });
+            }
+            text += "|\x1b[K\n";
+        }
+        text += &format!("+{}+\x1b[K\n", "-".repeat(self.width));
+        text
+    }
+}
+
+
+impl Observer for Dashboard
+{
+    fn get_sample_interval(&self) -> usize
+    {
+        self.every
+    }
+
+    fn uses_terminal(&self) -> bool
+    {
+        true
+    }
+
+    fn on_sample(&mut self, sim: &Simulation, event: usize) -> save_data::SaveResult<()>
+    {
+        let (x_max, y_max) = sim.get_box();
+        self.update(sim.get_particles(), sim.get_queue().get_len(), event, sim.get_time(), x_max, y_max);
+        Ok(())
+    }
+}
+
+
+fn push_sample(history: &mut VecDeque<f64>, value: f64)
+{
+    if history.len() == HISTORY
+    {
+        history.pop_front();
+    }
+    history.push_back(value);
+}
+
+
+// Draws the values as bars, from the smallest to the largest.
+fn sparkline(history: &VecDeque<f64>) -> String
+{
+    let min = history.iter().cloned().fold(f64::INFINITY, f64::min);
+    let max = history.iter().cloned().fold(f64::NEG_INFINITY, f64::max);
+    history.iter().map(|v|
+    {
+        let level = if max > min { (v - min) / (max - min) } else { 0.5 };
+        SPARKS[((level * (SPARKS.len() - 1) as f64).round() as usize).min(SPARKS.len() - 1)]
+    }).collect()
+}
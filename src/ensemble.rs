@@ -0,0 +1,271 @@
+use ndarray::prelude::*;
+
+use crate::parallel;
+use crate::save_data;
+use crate::scenario::Scenario;
+
+
+// The output of one realization of an ensemble.
+// energy and speeds are laid out like the
+// arrays returned by simulation::evolve_system.
+pub struct Realization
+{
+    pub energy: Array2<f64>,
+    pub speeds: Array2<f64>,
+    pub metrics: Vec<f64>,
+}
+
+
+// Averages over all realizations of an ensemble. Every quantity
+// is followed by a row holding its standard error.
+pub struct EnsembleAverage
+{
+    pub energy: Array2<f64>,    // time, e_tot, e_tot_err, e_0, e_0_err, ...
+    pub speeds: Array2<f64>,    // speed, p_init, p_init_err, p_0, p_0_err, ...
+    pub metrics: Array2<f64>,   // One column: metric, metric_err, ...
+}
+
+
+// Repeats a scenario a number of times and averages the results.
+// Realization k is seeded with the seed of the scenario plus k,
+// wrapping around at u64::MAX.
+pub struct Ensemble
+{
+    name: String,
+    metrics: Vec<String>,
+    realizations: usize,
+    workers: usize,
+    time_samples: usize,
+    speed_bins: usize,
+}
+
+
+impl Ensemble
+{
+    // name is the filename prefix of the averaged output,
+    // metrics are the names of the scalars returned by each realization.
+    pub fn new(name: &str, metrics: &[&str], realizations: usize) -> Ensemble
+    {
+        assert!(realizations > 1, "An ensemble needs at least two realizations.");
+        Ensemble
+        {
+            name: name.to_owned(),
+            metrics: metrics.iter().map(|m| m.to_string()).collect(),
+            realizations,
+            workers: 1,
+            time_samples: 200,
+            speed_bins: 50,
+        }
+    }
+
+    pub fn set_workers(&mut self, workers: usize) -> &mut Ensemble
+    {
+        assert!(workers > 0, "An ensemble needs at least one worker.");
+        self.workers = workers;
+        self
+    }
+
+    // Sets the number of points in time the energy is averaged at,
+    // and the number of bins in the speed histograms.
+    pub fn set_resolution(&mut self, time_samples: usize, speed_bins: usize) -> &mut Ensemble
+    {
+        assert!(time_samples > 1 && speed_bins > 0);
+        self.time_samples = time_samples;
+        self.speed_bins = speed_bins;
+        self
+    }
+
+    // Runs every realization and writes the averages to file.
+    // run_realization gets the scenario and the index of the realization.
+    pub fn run<F>(&self, base: &Scenario, run_realization: F)
+        -> save_data::SaveResult<EnsembleAverage>
+        where F: Fn(&Scenario, usize) -> save_data::SaveResult<Realization> + Sync
+    {
+        let scenarios: Vec<Scenario> = (0..self.realizations).map(|k|
+        {
+            let mut scenario = base.clone();
+            scenario.name = format!("{}_realization_{:03}", self.name, k);
+            scenario.seed = base.seed.wrapping_add(k as u64);
+            scenario
+        }).collect();
+
+        let realizations = parallel::run_parallel(self.realizations, self.workers,
+            |k| run_realization(&scenarios[k], k))
+            .into_iter().collect::<Result<Vec<Realization>, _>>()?;
+        for r in realizations.iter()
+        {
+            assert_eq!(r.metrics.len(), self.metrics.len(),
+                "A realization returned the wrong number of metrics.");
+        }
+
+        let average = EnsembleAverage
+        {
+            energy: average_energy(&realizations, self.time_samples),
+            speeds: average_speed_distributions(&realizations, self.speed_bins),
+            metrics: average_metrics(&realizations),
+        };
+
+        let species = &base.species_names;
+        let mut names = vec!["time".to_owned()];
+        names.extend(with_errors(&["e_tot".to_owned()]));
+        names.extend(with_errors(&species.iter().map(|name| format!("e_{}", name)).collect::<Vec<_>>()));
+        save_data::table_to_file(&names, &average.energy, &self.name, "ensemble_energy")?;
+
+        let mut rows = vec!["p_init".to_owned()];
+        rows.extend(species.iter().map(|name| format!("p_{}", name)));
+        let mut names = vec!["v".to_owned()];
+        names.extend(with_errors(&rows));
+        save_data::table_to_file(&names, &average.speeds, &self.name, "ensemble_speeds")?;
+
+        save_data::table_to_file(&with_errors(&self.metrics),
+            &average.metrics, &self.name, "ensemble_metrics")?;
+
+        // The metrics of every realization, with the seed
+        // needed to repeat it.
+        let mut table = Array2::zeros((self.metrics.len(), self.realizations));
+        for (k, r) in realizations.iter().enumerate()
+        {
+            for (j, metric) in r.metrics.iter().enumerate()
+            {
+                table[[j, k]] = *metric;
+            }
+        }
+        let seeds: Vec<u64> = scenarios.iter().map(|s| s.seed).collect();
+        save_data::realizations_to_file(&seeds, &self.metrics, &table, &self.name)?;
+
+        Ok(average)
+    }
+}
+
+
+// Returns the names followed by the names of their errors.
+fn with_errors(names: &[String]) -> Vec<String>
+{
+    let mut out = Vec::with_capacity(2*names.len());
+    for name in names.iter()
+    {
+        out.push(name.clone());
+        out.push(format!("{}_err", name));
+    }
+    out
+}
+
+
+// Returns the mean of the values and the standard error of the mean.
+pub fn mean_and_error(values: &[f64]) -> (f64, f64)
+{
+    let n = values.len() as f64;
+    let mean = values.iter().sum::<f64>() / n;
+    if values.len() < 2
+    {
+        return (mean, f64::NAN);
+    }
+    let variance = values.iter().map(|v| (v - mean).powi(2)).sum::<f64>() / (n - 1.);
+    (mean, (variance / n).sqrt())
+}
+
+
+// Averages the energy of all realizations at evenly spaced points in
+// time, from the start until the end of the shortest realization.
+// Between events the energy is constant, so the value at time t is
+// the one recorded at the last event before t.
+pub fn average_energy(realizations: &[Realization], samples: usize) -> Array2<f64>
+{
+    let rows = realizations[0].energy.nrows();
+    let lengths: Vec<usize> = realizations.iter().map(|r| r.energy.ncols()).collect();
+    let t_0 = realizations[0].energy[[0, 0]];
+    let t_end = realizations.iter().zip(lengths.iter())
+        .map(|(r, len)| r.energy[[0, len - 1]])
+        .fold(f64::INFINITY, f64::min);
+
+    let times = Array::linspace(t_0, t_end, samples);
+    let mut average = Array2::zeros((1 + 2*(rows - 1), samples));
+    let mut events = vec![0; realizations.len()];
+    let mut values = vec![0.; realizations.len()];
+
+    for (s, t) in times.iter().enumerate()
+    {
+        average[[0, s]] = *t;
+        for (k, r) in realizations.iter().enumerate()
+        {
+            while events[k] + 1 < lengths[k] && r.energy[[0, events[k] + 1]] <= *t
+            {
+                events[k] += 1;
+            }
+        }
+        for row in 1..rows
+        {
+            for (k, r) in realizations.iter().enumerate()
+            {
+                values[k] = r.energy[[row, events[k]]];
+            }
+            let (mean, error) = mean_and_error(&values);
+            average[[2*row - 1, s]] = mean;
+            average[[2*row, s]] = error;
+        }
+    }
+    average
+}
+
+
+// Makes a normalized histogram of each row of speeds in every
+// realization, and averages them. The rows are padded with NaN,
+// which are not counted. All histograms share the same bins.
+pub fn average_speed_distributions(realizations: &[Realization], bins: usize)
+    -> Array2<f64>
+{
+    let rows = realizations[0].speeds.nrows();
+    let v_max = realizations.iter()
+        .flat_map(|r| r.speeds.iter())
+        .filter(|v| v.is_finite())
+        .fold(0., |a: f64, v| a.max(*v));
+    let width = if v_max > 0. { v_max / bins as f64 } else { 1. };
+
+    let mut average = Array2::zeros((1 + 2*rows, bins));
+    for b in 0..bins
+    {
+        average[[0, b]] = (b as f64 + 0.5) * width;
+    }
+
+    let mut histograms = Array3::<f64>::zeros((realizations.len(), rows, bins));
+    for (k, r) in realizations.iter().enumerate()
+    {
+        for row in 0..rows
+        {
+            let speeds: Vec<f64> = r.speeds.row(row).iter()
+                .cloned().filter(|v| v.is_finite()).collect();
+            for v in speeds.iter()
+            {
+                let b = ((v / width) as usize).min(bins - 1);
+                histograms[[k, row, b]] += 1. / (speeds.len() as f64 * width);
+            }
+        }
+    }
+
+    for row in 0..rows
+    {
+        for b in 0..bins
+        {
+            let values = histograms.slice(s![.., row, b]).to_vec();
+            let (mean, error) = mean_and_error(&values);
+            average[[1 + 2*row, b]] = mean;
+            average[[2 + 2*row, b]] = error;
+        }
+    }
+    average
+}
+
+
+pub fn average_metrics(realizations: &[Realization]) -> Array2<f64>
+{
+    let n = realizations[0].metrics.len();
+    let mut average = Array2::zeros((2*n, 1));
+    for j in 0..n
+    {
+        let values: Vec<f64> = realizations.iter().map(|r| r.metrics[j]).collect();
+        let (mean, error) = mean_and_error(&values);
+        average[[2*j, 0]] = mean;
+        average[[2*j + 1, 0]] = error;
+    }
+    average
+}
@@ -0,0 +1,139 @@
+use std::fs::File;
+use std::io::{BufReader, BufWriter, Read, Write};
+use std::path::PathBuf;
+use std::convert::TryInto;
+
+use crate::collisions::CollisionOutcome;
+use crate::observer::Observer;
+use crate::simulation::Simulation;
+use crate::save_data;
+
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum EventLogFormat
+{
+    Csv,        // <filename>_events.csv, tab separated like the other data files
+    Binary,     // <filename>_events.bin, see write_binary_record
+}
+
+
+impl EventLogFormat
+{
+    pub fn suffix(&self) -> &'static str
+    {
+        match self
+        {
+            EventLogFormat::Csv => "_events.csv",
+            EventLogFormat::Binary => "_events.bin",
+        }
+    }
+}
+
+
+// Writes one record for every resolved collision, so that
+// collision statistics can be computed after the run.
+pub struct EventLog
+{
+    f: BufWriter<File>,
+    format: EventLogFormat,
+    events: usize,
+    path: PathBuf,
+}
+
+
+impl EventLog
+{
+    pub fn new(filename: &str, format: EventLogFormat) -> save_data::SaveResult<EventLog>
+    {
+        let path = save_data::data_path(filename, format.suffix())?;
+        let mut f = BufWriter::new(File::create(&path)?);
+        if format == EventLogFormat::Csv
+        {
+            writeln!(f, "time\tparticle_1\tparticle_2\tu_before\tu_after\timpulse\tenergy_loss")?;
+        }
+        Ok(EventLog { f, format, events: 0, path })
+    }
+
+    pub fn record(&mut self, c: &CollisionOutcome) -> save_data::SaveResult<()>
+    {
+        match self.format
+        {
+            EventLogFormat::Csv => writeln!(self.f, "{:?}\t{}\t{}\t{:?}\t{:?}\t{:?}\t{:?}",
+                c.time, c.particle_1, c.particle_2, c.normal_velocity_before,
+                c.normal_velocity_after, c.impulse, c.energy_loss)?,
+            EventLogFormat::Binary => write_binary_record(&mut self.f, c)?,
+        }
+        self.events += 1;
+        Ok(())
+    }
+
+    // Flushes the file, and reports where it was written.
+    pub fn finish(&mut self) -> save_data::SaveResult<()>
+    {
+        self.f.flush()?;
+        println!("{} events saved succesfully to file:\n{}", self.events, self.path.display());
+        Ok(())
+    }
+}
+
+
+impl Observer for EventLog
+{
+    fn needs_positions(&self) -> bool
+    {
+        false
+    }
+
+    fn on_event(&mut self, _sim: &Simulation, _event: usize, outcome: &CollisionOutcome)
+        -> save_data::SaveResult<()>
+    {
+        self.record(outcome)
+    }
+
+    fn on_finish(&mut self, _sim: &Simulation, _events: usize) -> save_data::SaveResult<()>
+    {
+        self.finish()
+    }
+}
+
+
+// A binary record is 48 bytes, all little endian: the time as
+// f64, the two indices as i32, and the normal velocities before
+// and after, the impulse and the energy loss as f64.
+fn write_binary_record<W: Write>(f: &mut W, c: &CollisionOutcome) -> save_data::SaveResult<()>
+{
+    f.write_all(&c.time.to_le_bytes())?;
+    f.write_all(&c.particle_1.to_le_bytes())?;
+    f.write_all(&c.particle_2.to_le_bytes())?;
+    for value in [c.normal_velocity_before, c.normal_velocity_after, c.impulse, c.energy_loss]
+    {
+        f.write_all(&value.to_le_bytes())?;
+    }
+    Ok(())
+}
+
+
+// Reads an event log written in the binary format.
+pub fn read_binary_event_log(filename: &str) -> save_data::SaveResult<Vec<CollisionOutcome>>
+{
+    let path = save_data::data_path(filename, EventLogFormat::Binary.suffix())?;
+    let mut bytes = Vec::new();
+    BufReader::new(File::open(&path)?).read_to_end(&mut bytes)?;
+    if !bytes.len().is_multiple_of(48)
+    {
+        return Err(format!("{} is not a whole number of event records.", path.display()).into());
+    }
+
+    let f64_at = |record: &[u8], k: usize| f64::from_le_bytes(record[k..k + 8].try_into().unwrap());
+    let i32_at = |record: &[u8], k: usize| i32::from_le_bytes(record[k..k + 4].try_into().unwrap());
+    Ok(bytes.chunks_exact(48).map(|record| CollisionOutcome
+    {
+        time: f64_at(record, 0),
+        particle_1: i32_at(record, 8),
+        particle_2: i32_at(record, 12),
+        normal_velocity_before: f64_at(record, 16),
+        normal_velocity_after: f64_at(record, 24),
+        impulse: f64_at(record, 32),
+        energy_loss: f64_at(record, 40),
+    }).collect())
+}
@@ -0,0 +1,195 @@
+use crate::particle;
+use crate::collisions::{self, Collision, EventQueue};
+
+
+// Which queue a simulation keeps its upcoming collisions in.
+#[derive(Clone, Copy, Debug, PartialEq, Default)]
+pub enum QueueKind
+{
+    #[default]
+    Heap,       // collisions::CollisionQueue
+    Indexed,    // IndexedQueue
+}
+
+
+impl QueueKind
+{
+    pub fn make_queue(&self, n: usize) -> Box<dyn EventQueue>
+    {
+        match self
+        {
+            QueueKind::Heap => Box::new(collisions::CollisionQueue::new()),
+            QueueKind::Indexed => Box::new(IndexedQueue::new(n)),
+        }
+    }
+}
+
+
+const NOT_QUEUED: usize = usize::MAX;
+
+
+// Keeps only the earliest collision of each particle, in a binary
+// heap of particle indices that knows where every particle is, so
+// the collision of a particle can be replaced or removed in place.
+// The queue therefore never holds more than one entry per particle.
+//
+// When a particle collides, the entries of other particles that
+// expected to collide with it become outdated. They are kept until
+// they reach the front of the queue, and the earliest collision of
+// their particle is found again then. That is soon enough, because
+// every collision that could come earlier involves a particle that
+// has collided since, and was found when that particle was updated.
+pub struct IndexedQueue
+{
+    events: Vec<Option<Collision>>,    // Earliest collision of each particle
+    heap: Vec<usize>,                  // Particles, earliest collision first
+    position: Vec<usize>,              // Index of each particle in heap
+}
+
+
+impl IndexedQueue
+{
+    pub fn new(n: usize) -> IndexedQueue
+    {
+        IndexedQueue
+        {
+            events: (0..n).map(|_| None).collect(),
+            heap: Vec::with_capacity(n),
+            position: vec![NOT_QUEUED; n],
+        }
+    }
+
+    fn get_time(&self, k: usize) -> f64
+    {
+        self.events[self.heap[k]].as_ref().unwrap().get_time()
+    }
+
+    // Sets the earliest collision of particle i, or removes it.
+    fn set_event(&mut self, i: usize, c: Option<Collision>)
+    {
+        if c.is_none()
+        {
+            self.remove(i);
+            return;
+        }
+        self.events[i] = c;
+        if self.position[i] == NOT_QUEUED
+        {
+            self.heap.push(i);
+            self.position[i] = self.heap.len() - 1;
+        }
+        self.sift_up(self.position[i]);
+        self.sift_down(self.position[i]);
+    }
+
+    // Takes particle i out of the heap, and returns its collision.
+    fn remove(&mut self, i: usize) -> Option<Collision>
+    {
+        let k = self.position[i];
+        if k == NOT_QUEUED
+        {
+            return None;
+        }
+        let last = self.heap.len() - 1;
+        self.swap(k, last);
+        self.heap.pop();
+        self.position[i] = NOT_QUEUED;
+        if k < self.heap.len()
+        {
+            // The particle moved into the gap may belong further up or down.
+            let moved = self.heap[k];
+            self.sift_up(k);
+            self.sift_down(self.position[moved]);
+        }
+        self.events[i].take()
+    }
+
+    fn swap(&mut self, a: usize, b: usize)
+    {
+        self.heap.swap(a, b);
+        self.position[self.heap[a]] = a;
+        self.position[self.heap[b]] = b;
+    }
+
+    fn sift_up(&mut self, mut k: usize)
+    {
+        while k > 0
+        {
+            let parent = (k - 1) / 2;
+            if self.get_time(k) >= self.get_time(parent)
+            {
+                break;
+            }
+            self.swap(k, parent);
+            k = parent;
+        }
+    }
+
+    fn sift_down(&mut self, mut k: usize)
+    {
+        loop
+        {
+            let mut first = k;
+            for child in [2*k + 1, 2*k + 2]
+            {
+                if child < self.heap.len() && self.get_time(child) < self.get_time(first)
+                {
+                    first = child;
+                }
+            }
+            if first == k
+            {
+                break;
+            }
+            self.swap(k, first);
+            k = first;
+        }
+    }
+}
+
+
+impl EventQueue for IndexedQueue
+{
+    fn get_len(&self) -> usize
+    {
+        self.heap.len()
+    }
+
+    fn peek_next(&self) -> Option<&Collision>
+    {
+        self.heap.first().map(|i| self.events[*i].as_ref().unwrap())
+    }
+
+    fn pop_next(&mut self) -> Collision
+    {
+        assert!(!self.heap.is_empty());
+        self.remove(self.heap[0]).unwrap()
+    }
+
+    // The particle of an outdated entry has not collided since it
+    // was found, so its earliest collision is found again.
+    fn discard_next(&mut self, particles: &particle::Particles, t: f64, x_max: f64, y_max: f64)
+    {
+        let c = self.pop_next();
+        self.add_new_collisions(particles, c.get_particle_1() as usize, t, x_max, y_max);
+    }
+
+    // Replaces the collision of particle i with the earliest one.
+    fn insert_collisions(&mut self, i: usize, collisions: Vec<Collision>)
+    {
+        let mut earliest: Option<Collision> = None;
+        for c in collisions
+        {
+            if earliest.as_ref().is_none_or(|e| c.get_time() < e.get_time())
+            {
+                earliest = Some(c);
+            }
+        }
+        self.set_event(i, earliest);
+    }
+
+    fn iter(&self) -> Box<dyn Iterator<Item = &Collision> + '_>
+    {
+        Box::new(self.heap.iter().map(move |i| self.events[*i].as_ref().unwrap()))
+    }
+}
@@ -0,0 +1,36 @@
+// Event-driven simulation of a granular gas of hard disks.
+//
+// A system is described by a Scenario, or built directly with
+// particle::generate_particles, and evolved with a
+// simulation::Simulation, either event by event or with
+// simulation::evolve_system. Snapshots and other measurements
+// are observer::Observers, which are called while it runs, and
+// everything is saved with save_data, which can also load
+// particles written by an earlier run.
+
+pub mod particle;
+pub mod collisions;
+pub mod indexed_queue;
+pub mod parameters;
+pub mod plotting;
+pub mod simulation;
+pub mod observer;
+pub mod stop;
+pub mod save_data;
+pub mod scenario;
+pub mod sweep;
+pub mod parallel;
+pub mod ensemble;
+pub mod compression;
+pub mod trajectory;
+pub mod event_log;
+pub mod render;
+pub mod dashboard;
+
+pub use particle::Particles;
+pub use collisions::{Collision, CollisionOutcome, CollisionQueue, EventQueue};
+pub use scenario::Scenario;
+pub use simulation::{evolve_system, CollisionModel, Simulation};
+pub use observer::Observer;
+pub use stop::StopCondition;
+pub use save_data::SaveResult;
@@ -1,20 +1,17 @@
-mod particle;
-mod collisions;
-mod parameters;
-mod tests;
-mod plotting;
-mod simulation;
+use granular_gas::save_data;
+
 mod tasks;
-mod save_data;
+mod tests;
 
-fn main() 
+fn main() -> save_data::SaveResult<()>
 {
     let arg = "sim";
     match arg
     {
         "test" => tests::test_main(),
-        "sim" => tasks::tasks_main(),
+        "sim" => tasks::tasks_main()?,
         _ => panic!("That was probably a typo."),
     }
+    Ok(())
 }
 
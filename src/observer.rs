@@ -0,0 +1,354 @@
+use ndarray::prelude::*;
+
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::PathBuf;
+
+use crate::collisions::CollisionOutcome;
+use crate::plotting;
+use crate::simulation::{self, Simulation};
+use crate::save_data;
+
+
+// Something that watches a run of simulation::run. Every method
+// has a default that does nothing, so an observer only implements
+// what it needs. A sample is the state of the system after a
+// number of events that is a multiple of get_sample_interval,
+// starting with the state before the first event.
+pub trait Observer
+{
+    // Number of events between samples.
+    fn get_sample_interval(&self) -> usize
+    {
+        1
+    }
+
+    // Whether on_sample looks at the positions of the particles,
+    // which are then brought up to date before it is called.
+    // Positions seen by on_event are only up to date for the
+    // particles that collided.
+    fn needs_positions(&self) -> bool
+    {
+        true
+    }
+
+    // Whether the observer draws to the terminal itself,
+    // in which case the status bar is not shown.
+    fn uses_terminal(&self) -> bool
+    {
+        false
+    }
+
+    // Called once, before the first sample.
+    fn on_start(&mut self, _sim: &Simulation) -> save_data::SaveResult<()>
+    {
+        Ok(())
+    }
+
+    // Called after every resolved event. event is the number
+    // of events resolved in this run, including this one.
+    fn on_event(&mut self, _sim: &Simulation, _event: usize, _outcome: &CollisionOutcome)
+        -> save_data::SaveResult<()>
+    {
+        Ok(())
+    }
+
+    fn on_sample(&mut self, _sim: &Simulation, _event: usize) -> save_data::SaveResult<()>
+    {
+        Ok(())
+    }
+
+    // Called once, after the last event. events is the
+    // number of events that were resolved in this run.
+    fn on_finish(&mut self, _sim: &Simulation, _events: usize) -> save_data::SaveResult<()>
+    {
+        Ok(())
+    }
+}
+
+
+// Records the time and energy of every sample, in the layout
+// returned by simulation::evolve_system:
+// index 0: time of collisions
+// index 1: kinetic energy at these times
+// index 2 + k: kinetic energy of species k
+// The state after the last event is always recorded. With a
+// limit, every other sample is dropped whenever the limit is
+// reached, and the interval between samples is doubled, so
+// a run of any length fits in a fixed amount of memory.
+pub struct EnergyRecorder
+{
+    every: usize,
+    limit: Option<usize>,
+    rows: usize,
+    samples: Vec<f64>,      // One sample after the other
+    last_event: usize,
+}
+
+
+impl EnergyRecorder
+{
+    // Records a sample every `every` events.
+    pub fn new(every: usize) -> EnergyRecorder
+    {
+        assert!(every > 0, "Samples must be taken at least every event.");
+        EnergyRecorder { every, limit: None, rows: 0, samples: Vec::new(), last_event: 0 }
+    }
+
+    // Keeps at most limit samples, which must be even.
+    pub fn with_limit(every: usize, limit: usize) -> EnergyRecorder
+    {
+        assert!(limit >= 2 && limit.is_multiple_of(2), "The limit must be even.");
+        EnergyRecorder { limit: Some(limit), ..EnergyRecorder::new(every) }
+    }
+
+    pub fn get_sample_count(&self) -> usize
+    {
+        self.samples.len().checked_div(self.rows).unwrap_or(0)
+    }
+
+    fn record(&mut self, sim: &Simulation, event: usize)
+    {
+        if self.limit == Some(self.get_sample_count())
+        {
+            // Samples are taken at multiples of every, so keeping
+            // the even ones leaves the multiples of 2 every.
+            let rows = self.rows;
+            let kept: Vec<f64> = self.samples.chunks(rows).step_by(2).flatten().cloned().collect();
+            self.samples = kept;
+            self.every *= 2;
+        }
+        self.samples.push(sim.get_time());
+        self.samples.push(sim.get_kinetic_energy());
+        for k in 0..sim.get_particles().get_species_count()
+        {
+            self.samples.push(sim.get_kinetic_energy_for_species(k));
+        }
+        self.last_event = event;
+    }
+
+    pub fn into_data(self) -> Array2<f64>
+    {
+        let samples = self.get_sample_count();
+        Array2::from_shape_vec((samples, self.rows), self.samples).unwrap()
+            .reversed_axes().as_standard_layout().to_owned()
+    }
+}
+
+
+impl Observer for EnergyRecorder
+{
+    fn needs_positions(&self) -> bool
+    {
+        false
+    }
+
+    fn get_sample_interval(&self) -> usize
+    {
+        self.every
+    }
+
+    fn on_start(&mut self, sim: &Simulation) -> save_data::SaveResult<()>
+    {
+        self.rows = 2 + sim.get_particles().get_species_count();
+        self.samples.clear();
+        Ok(())
+    }
+
+    fn on_sample(&mut self, sim: &Simulation, event: usize) -> save_data::SaveResult<()>
+    {
+        self.record(sim, event);
+        Ok(())
+    }
+
+    fn on_finish(&mut self, sim: &Simulation, events: usize) -> save_data::SaveResult<()>
+    {
+        if events != self.last_event
+        {
+            self.record(sim, events);
+        }
+        Ok(())
+    }
+}
+
+
+// Writes the time and energy of every sample to
+// <filename>_energy.csv while the simulation runs, in the
+// format of save_data::energy_to_file. The state after the
+// last event is always written.
+pub struct EnergyWriter
+{
+    filename: String,
+    every: usize,
+    f: Option<BufWriter<File>>,
+    path: PathBuf,
+    samples: usize,
+    last_event: usize,
+}
+
+
+impl EnergyWriter
+{
+    // Writes a sample every `every` events. The file is
+    // created when the run starts.
+    pub fn new(filename: &str, every: usize) -> EnergyWriter
+    {
+        assert!(every > 0, "Samples must be taken at least every event.");
+        EnergyWriter
+        {
+            filename: filename.to_owned(),
+            every,
+            f: None,
+            path: PathBuf::new(),
+            samples: 0,
+            last_event: 0,
+        }
+    }
+
+    fn write_sample(&mut self, sim: &Simulation, event: usize) -> save_data::SaveResult<()>
+    {
+        let f = self.f.as_mut().unwrap();
+        write!(f, "{:?}\t{:?}\t", sim.get_time(), sim.get_kinetic_energy())?;
+        for k in 0..sim.get_particles().get_species_count()
+        {
+            write!(f, "{:?}\t", sim.get_kinetic_energy_for_species(k))?;
+        }
+        writeln!(f)?;
+        self.samples += 1;
+        self.last_event = event;
+        Ok(())
+    }
+}
+
+
+impl Observer for EnergyWriter
+{
+    fn needs_positions(&self) -> bool
+    {
+        false
+    }
+
+    fn get_sample_interval(&self) -> usize
+    {
+        self.every
+    }
+
+    fn on_start(&mut self, sim: &Simulation) -> save_data::SaveResult<()>
+    {
+        self.path = save_data::data_path(&self.filename, "_energy.csv")?;
+        let mut f = BufWriter::new(File::create(&self.path)?);
+        write!(f, "time\te_tot")?;
+        for name in sim.get_particles().species_names.iter()
+        {
+            write!(f, "\te_{}", name)?;
+        }
+        writeln!(f)?;
+        self.f = Some(f);
+        self.samples = 0;
+        Ok(())
+    }
+
+    fn on_sample(&mut self, sim: &Simulation, event: usize) -> save_data::SaveResult<()>
+    {
+        self.write_sample(sim, event)
+    }
+
+    fn on_finish(&mut self, sim: &Simulation, events: usize) -> save_data::SaveResult<()>
+    {
+        if events != self.last_event
+        {
+            self.write_sample(sim, events)?;
+        }
+        self.f.take().unwrap().flush()?;
+        println!("{} energy samples saved succesfully to file:\n{}", self.samples, self.path.display());
+        Ok(())
+    }
+}
+
+
+// Records the speed of each particle before and after the run.
+// Row 0 holds the initial speeds, and row 1 + k the final speeds
+// of species k, padded with NaN.
+pub struct SpeedRecorder
+{
+    data: Array2<f64>,
+}
+
+
+impl SpeedRecorder
+{
+    pub fn new() -> SpeedRecorder
+    {
+        SpeedRecorder { data: Array2::zeros((0, 0)) }
+    }
+
+    pub fn into_data(self) -> Array2<f64>
+    {
+        self.data
+    }
+}
+
+
+impl Default for SpeedRecorder
+{
+    fn default() -> SpeedRecorder
+    {
+        SpeedRecorder::new()
+    }
+}
+
+
+impl Observer for SpeedRecorder
+{
+    fn needs_positions(&self) -> bool
+    {
+        false
+    }
+
+    fn on_start(&mut self, sim: &Simulation) -> save_data::SaveResult<()>
+    {
+        let p = sim.get_particles();
+        self.data = Array2::from_elem((1 + p.get_species_count(), p.get_len()), f64::NAN);
+        for j in 0..p.get_len()
+        {
+            self.data[[0, j]] = p.get_speed(j);
+        }
+        Ok(())
+    }
+
+    fn on_finish(&mut self, sim: &Simulation, _events: usize) -> save_data::SaveResult<()>
+    {
+        let p = sim.get_particles();
+        let mut filled = vec![0; p.get_species_count()];
+        for j in 0..p.get_len()
+        {
+            let k = p.species[j];
+            self.data[[k + 1, filled[k]]] = p.get_speed(j);
+            filled[k] += 1;
+        }
+        Ok(())
+    }
+}
+
+
+// Prints every particle and the whole queue, and plots the
+// positions, before every event. Only useful for tiny systems.
+pub struct TestPrinter;
+
+
+impl Observer for TestPrinter
+{
+    fn uses_terminal(&self) -> bool
+    {
+        true
+    }
+
+    fn on_sample(&mut self, sim: &Simulation, _event: usize) -> save_data::SaveResult<()>
+    {
+        let (x_max, y_max) = sim.get_box();
+        simulation::print_particle_stats(sim.get_particles());
+        simulation::print_collision_stats(sim.get_queue());
+        plotting::plot_positions(sim.get_particles(), x_max, y_max, &plotting::PlotOutput::Show);
+        Ok(())
+    }
+}
@@ -0,0 +1,141 @@
+use std::cell::{Cell, RefCell};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::mpsc;
+use std::thread;
+
+use rayon::prelude::*;
+
+use crate::simulation;
+
+
+// Returns the number of threads that can run at the same time,
+// or 1 if this can not be determined.
+pub fn available_workers() -> usize
+{
+    thread::available_parallelism().map(|n| n.get()).unwrap_or(1)
+}
+
+
+thread_local!
+{
+    // Number of threads that map_ordered may use on this
+    // thread, where 0 means available_workers. Set to 1 in
+    // threads that already run in parallel to others.
+    static WORKERS: Cell<usize> = const { Cell::new(0) };
+
+    // The pool map_ordered runs on, with its number of threads.
+    // It is kept between calls, so that its threads are only
+    // started once, and it is cheap enough to use after every event.
+    static POOL: RefCell<Option<(usize, rayon::ThreadPool)>> = const { RefCell::new(None) };
+}
+
+
+pub fn set_workers(workers: usize)
+{
+    WORKERS.with(|w| w.set(workers));
+}
+
+
+pub fn get_workers() -> usize
+{
+    match WORKERS.with(|w| w.get())
+    {
+        0 => available_workers(),
+        workers => workers,
+    }
+}
+
+
+// Returns f(0), f(1), ..., f(n - 1), computed on a pool of
+// get_workers threads. The results are in the same order
+// whatever the number of threads.
+pub fn map_ordered<T, F>(n: usize, f: F) -> Vec<T>
+    where T: Send, F: Fn(usize) -> T + Sync + Send
+{
+    let workers = get_workers().min(n.max(1));
+    if workers == 1
+    {
+        return (0..n).map(f).collect();
+    }
+
+    POOL.with(|pool|
+    {
+        let mut pool = pool.borrow_mut();
+        if pool.as_ref().is_none_or(|(w, _)| *w != workers)
+        {
+            let threads = rayon::ThreadPoolBuilder::new()
+                .num_threads(workers)
+                .start_handler(|_| set_workers(1))
+                .build()
+                .expect("Could not start the worker threads.");
+            *pool = Some((workers, threads));
+        }
+        let (_, threads) = pool.as_ref().unwrap();
+        // A few blocks per thread, so that the threads share the work
+        // evenly without handing out every index on its own.
+        let block = n.div_ceil(4*workers);
+        threads.install(|| (0..n).into_par_iter().with_min_len(block).map(f).collect())
+    })
+}
+
+
+// Runs job(0), job(1), ..., job(jobs - 1) on a pool of at most
+// workers threads, and returns the results in the same order.
+// The status bar is turned off in the worker threads, so that
+// only the progress of the pool itself is printed.
+pub fn run_parallel<T, F>(jobs: usize, workers: usize, job: F) -> Vec<T>
+    where T: Send, F: Fn(usize) -> T + Sync
+{
+    let workers = workers.max(1).min(jobs.max(1));
+    if workers == 1
+    {
+        return (0..jobs).map(&job).collect();
+    }
+
+    let next_job = AtomicUsize::new(0);
+    let (sender, receiver) = mpsc::channel();
+    let mut results: Vec<Option<T>> = (0..jobs).map(|_| None).collect();
+
+    thread::scope(|scope|
+    {
+        for _ in 0..workers
+        {
+            let sender = sender.clone();
+            let next_job = &next_job;
+            let job = &job;
+            scope.spawn(move ||
+            {
+                simulation::set_status_bar(false);
+                set_workers(1);
+                loop
+                {
+                    let k = next_job.fetch_add(1, Ordering::SeqCst);
+                    if k >= jobs
+                    {
+                        break;
+                    }
+                    // The receiver only hangs up if the main thread panicked.
+                    if sender.send((k, job(k))).is_err()
+                    {
+                        break;
+                    }
+                }
+            });
+        }
+        // Only the workers hold senders now, so the loop
+        // below ends when the last worker is done.
+        drop(sender);
+
+        let mut done = 0;
+        for (k, result) in receiver.iter()
+        {
+            results[k] = Some(result);
+            done += 1;
+            println!("Finished run {} ({} of {} done).", k, done, jobs);
+        }
+    });
+
+    results.into_iter()
+        .map(|r| r.expect("A worker thread stopped before finishing its run."))
+        .collect()
+}
@@ -9,3 +9,28 @@ pub const V_0: f64 = 1.;
 pub const NUMBER_OF_COLLISIONS: usize = 20000;
 pub const T_0: f64 = 0.;
 pub const TC_DT: f64 = 1e-5;
+
+// Number of energy samples that evolve_system keeps in memory.
+// Longer runs are recorded at longer intervals.
+pub const MAX_ENERGY_SAMPLES: usize = 100000;
+
+// Number of events between exact recomputations of the energy,
+// which is otherwise updated from the colliding particles only,
+// and the relative drift that is tolerated between them.
+pub const ENERGY_CHECK_INTERVAL: usize = 10000;
+pub const ENERGY_DRIFT_TOLERANCE: f64 = 1e-9;
+
+// Number of particles from which the collisions of a particle
+// are found on several threads after every event.
+pub const PARALLEL_PAIR_THRESHOLD: usize = 2000;
+
+// Seed of the random number generator, unless another is given.
+pub const SEED: u64 = 0;
+
+// Number of times a particle may be placed at an overlapping
+// position before Placement::Random gives up.
+pub const MAX_PLACEMENT_FAILURES: usize = 1000000;
+
+// Directory that data is written to, unless another is set,
+// relative to the working directory of the program.
+pub const DATA_DIR: &str = "data";
@@ -4,12 +4,15 @@ extern crate ndarray_csv;
 
 use ndarray::prelude::*;
 use ndarray::{stack_new_axis};
-use ndarray_rand::rand::{Rng, thread_rng};
-use ndarray_rand::rand_distr::Uniform;
+use ndarray_rand::rand::Rng;
+use ndarray_rand::rand::seq::SliceRandom;
+use ndarray_rand::rand::rngs::StdRng;
+use ndarray_rand::rand_distr::{LogNormal, Normal, Uniform};
 use ndarray_rand::RandomExt;
 
 use crate::parameters;
 use crate::simulation;
+use crate::compression;
 
 
 pub struct Particles
@@ -18,8 +21,13 @@ pub struct Particles
     pub vel: Array2<f64>,
     pub r: Array1<f64>,
     pub m: Array1<f64>,
-    pub collision_count: Array1<u32> // Number of times each 
+    pub collision_count: Array1<u32>, // Number of times each 
                                     // particle has collided
+    pub species: Array1<usize>,     // Index of the species of 
+                                    // each particle in species_names
+    pub species_names: Vec<String>,
+    pub sync_time: Array1<f64>,     // Time at which pos of each particle
+                                    // was last brought up to date
 }
 
 
@@ -35,6 +43,9 @@ impl Particles
             r: self.r.to_owned(),
             m: self.m.to_owned(),
             collision_count: self.collision_count.to_owned(),
+            species: self.species.to_owned(),
+            species_names: self.species_names.clone(),
+            sync_time: self.sync_time.to_owned(),
         }
     }
 
@@ -60,7 +71,7 @@ impl Particles
         {
             cc += self.get_collision_count(i as i32) as f64;
         }
-        cc
+        cc / self.get_len() as f64
     }
 
     pub fn get_kinetic_energy(&self, i: usize) -> f64
@@ -79,12 +90,12 @@ impl Particles
         energy
     }
 
-    pub fn get_kinetic_energy_for_mass(&self, mass: f64) -> f64
+    pub fn get_kinetic_energy_for_species(&self, species: usize) -> f64
     {
         let mut energy: f64 = 0.;
         for i in 0..self.get_len()
         {
-            if (self.get_mass(i) - mass).abs() <= 1e-8
+            if self.species[i] == species
             {
                 energy += self.get_kinetic_energy(i);
             }
@@ -92,6 +103,67 @@ impl Particles
         energy
     }
 
+    pub fn get_species_count(&self) -> usize
+    {
+        self.species_names.len()
+    }
+
+    pub fn get_species_name(&self, i: usize) -> &str
+    {
+        &self.species_names[self.species[i]]
+    }
+
+
+    // Returns n_bins + 1 evenly spaced edges of bins that
+    // cover all radii, for analysing polydisperse systems.
+    pub fn get_size_bins(&self, n_bins: usize) -> Array1<f64>
+    {
+        let r_min = self.r.fold(f64::INFINITY, |a, r| a.min(*r));
+        let r_max = self.r.fold(0., |a: f64, r| a.max(*r));
+        // Widen the last bin slightly, so that it includes r_max.
+        Array::linspace(r_min, r_max + 1e-9 * r_max.max(1e-12), n_bins + 1)
+    }
+
+    // Returns the index of the bin that particle i belongs in,
+    // or None if its radius is outside the bin edges.
+    pub fn get_size_bin(&self, i: usize, edges: &Array1<f64>) -> Option<usize>
+    {
+        let r = self.r[i];
+        if r < edges[0] || r >= edges[edges.len() - 1]
+        {
+            return None;
+        }
+        (0..edges.len() - 1).find(|b| r < edges[b + 1])
+    }
+
+    // Returns the kinetic energy of the particles in each size bin.
+    pub fn get_kinetic_energy_per_size_bin(&self, edges: &Array1<f64>) -> Array1<f64>
+    {
+        let mut energy = Array1::zeros(edges.len() - 1);
+        for i in 0..self.get_len()
+        {
+            if let Some(b) = self.get_size_bin(i, edges)
+            {
+                energy[b] += self.get_kinetic_energy(i);
+            }
+        }
+        energy
+    }
+
+    // Returns the number of particles in each size bin.
+    pub fn get_count_per_size_bin(&self, edges: &Array1<f64>) -> Array1<usize>
+    {
+        let mut count = Array1::zeros(edges.len() - 1);
+        for i in 0..self.get_len()
+        {
+            if let Some(b) = self.get_size_bin(i, edges)
+            {
+                count[b] += 1;
+            }
+        }
+        count
+    }
+
 
     pub fn get_mass(&self, i: usize) -> f64
     {
@@ -127,27 +199,58 @@ impl Particles
     }
 
 
-    pub fn time_until_next_collisions(&self, i: usize, j: i32, x_max: f64, y_max: f64) 
+    // Returns the time from t until particle i collides with j, and j.
+    // Both particles are first moved on to t, so their positions do
+    // not need to be up to date.
+    pub fn time_until_next_collisions(&self, i: usize, j: i32, t: f64, x_max: f64, y_max: f64) 
         -> (f64, i32)
     {
         assert!(j >= -2, "Undefined index for particle 2 encountered.");
+        let (x, y) = self.get_position_at(i, t);
         match j 
         {
             -1 =>
                 (wall_collition_time(
-                    self.pos[[1, i]], self.vel[[1, i]], self.r[i], y_max), -1),
+                    y, self.vel[[1, i]], self.r[i], y_max), -1),
 
             -2 =>
                 (wall_collition_time(
-                    self.pos[[0, i]], self.vel[[0, i]], self.r[i], x_max), -2),
+                    x, self.vel[[0, i]], self.r[i], x_max), -2),
 
             _ =>
-                (particle_collision_time(
-                    &self.pos, &self.vel, &self.r, i, j as usize), j), 
+                (particle_collision_time(self, i, j as usize, t), j), 
         }
     } 
 
 
+    // Returns the position of particle i at time t.
+    pub fn get_position_at(&self, i: usize, t: f64) -> (f64, f64)
+    {
+        let dt = t - self.sync_time[i];
+        (self.pos[[0, i]] + self.vel[[0, i]] * dt, self.pos[[1, i]] + self.vel[[1, i]] * dt)
+    }
+
+
+    // Brings the position of particle i up to date at time t.
+    pub fn sync_particle(&mut self, i: usize, t: f64)
+    {
+        let (x, y) = self.get_position_at(i, t);
+        self.pos[[0, i]] = x;
+        self.pos[[1, i]] = y;
+        self.sync_time[i] = t;
+    }
+
+
+    // Brings the positions of all particles up to date at time t.
+    pub fn sync(&mut self, t: f64)
+    {
+        for i in 0..self.get_len()
+        {
+            self.sync_particle(i, t);
+        }
+    }
+
+
     // Propagates all particles in list for a time dt
     pub fn propagate(&mut self, dt: f64)
     {
@@ -156,6 +259,7 @@ impl Particles
             //assert!(self.is_within_box(i));
             self.pos[[0,i]] += self.vel[[0,i]] * dt;
             self.pos[[1,i]] += self.vel[[1,i]] * dt;
+            self.sync_time[i] += dt;
         }
     }
 
@@ -207,94 +311,361 @@ impl Particles
 }
 
 
+// Strategies for placing the particles in the box.
+#[derive(Clone, Debug)]
+pub enum Placement
+{
+    // Draw random positions for all particles, and redraw the position
+    // of each overlapping particle until it fits. Checks against all
+    // other particles, so this is slow for dense systems.
+    Random,
+    // Random sequential addition: particles are placed one at a time,
+    // largest first, and only checked against particles in the
+    // neighbouring cells. Panics if a particle is rejected more
+    // than max_failures times in a row.
+    RandomSequential { max_failures: usize },
+    // Place the particles on a square or hexagonal lattice,
+    // filling the box from the bottom. Species are mixed randomly.
+    SquareLattice,
+    HexagonalLattice,
+    // Place the particles by random sequential addition at reduced radii,
+    // giving a packing fraction of initial_fraction, and grow them back
+    // to their real size by Lubachevsky-Stillinger compression.
+    // See compression::compress for growth_rate.
+    Compressed { initial_fraction: f64, growth_rate: f64, max_events: usize },
+}
+
+
+// Distributions of the radii within one species. For continuous
+// distributions, the mass is the density times the area of the disk.
+#[derive(Clone, Debug)]
+pub enum SizeDistribution
+{
+    // Every particle has the radius and mass given for the species.
+    Fixed,
+    Uniform { min: f64, max: f64, density: f64 },
+    // Normal distribution, where negative radii are drawn again.
+    Gaussian { mean: f64, std_dev: f64, density: f64 },
+    // The logarithm of the radius is normal, with mean mu.
+    LogNormal { mu: f64, sigma: f64, density: f64 },
+}
+
+
+impl SizeDistribution
+{
+    // Returns the radius and mass of one particle. 
+    // r and m are those given for the species.
+    pub fn sample(&self, r: f64, m: f64, rng: &mut StdRng) -> (f64, f64)
+    {
+        let (radius, density) = match *self
+        {
+            SizeDistribution::Fixed => return (r, m),
+            SizeDistribution::Uniform { min, max, density } =>
+            {
+                assert!(0. <= min && min <= max, "Invalid radii: {} to {}", min, max);
+                (if min == max { min } else { rng.sample(Uniform::new(min, max)) }, density)
+            }
+            SizeDistribution::Gaussian { mean, std_dev, density } =>
+            {
+                assert!(mean > 0., "Gaussian radii need a positive mean.");
+                let normal = Normal::new(mean, std_dev).unwrap();
+                let mut radius = rng.sample(normal);
+                while radius <= 0.
+                {
+                    radius = rng.sample(normal);
+                }
+                (radius, density)
+            }
+            SizeDistribution::LogNormal { mu, sigma, density } =>
+                (rng.sample(LogNormal::new(mu, sigma).unwrap()), density),
+        };
+        (radius, density * std::f64::consts::PI * radius.powi(2))
+    }
+}
+
+
+// Distributions of the initial velocities of the particles.
+#[derive(Clone, Debug)]
+pub enum VelocityDistribution
+{
+    // Every particle has the same speed, in a random direction.
+    Fixed(f64),
+    // Maxwell-Boltzmann distribution at a temperature (with k_B = 1).
+    // Each velocity component is normal, with variance T/m.
+    MaxwellBoltzmann(f64),
+    // Maxwell-Boltzmann, with one temperature for each species.
+    MaxwellBoltzmannPerSpecies(Array1<f64>),
+}
+
+
+#[derive(Clone, Debug)]
+pub struct InitialVelocities
+{
+    pub distribution: VelocityDistribution,
+    // Subtract the velocity of the centre of mass from every particle.
+    pub zero_momentum: bool,
+    // Rescale all velocities to give exactly this kinetic energy.
+    // This is done after the momentum is removed.
+    pub total_energy: Option<f64>,
+}
+
+
+impl InitialVelocities
+{
+    // The original initial condition: speed v_0 in a random direction.
+    pub fn fixed(v_0: f64) -> InitialVelocities
+    {
+        InitialVelocities
+        {
+            distribution: VelocityDistribution::Fixed(v_0),
+            zero_momentum: false,
+            total_energy: None,
+        }
+    }
+
+    pub fn maxwell_boltzmann(temperature: f64) -> InitialVelocities
+    {
+        InitialVelocities
+        {
+            distribution: VelocityDistribution::MaxwellBoltzmann(temperature),
+            zero_momentum: true,
+            total_energy: None,
+        }
+    }
+}
+
+
+// Draws velocities for particles with the given masses and species.
+pub fn generate_velocities(
+    m: &Array1<f64>,
+    species: &Array1<usize>,
+    initial_velocities: &InitialVelocities,
+    rng: &mut StdRng)
+    -> Array2<f64>
+{
+    let n = m.len();
+    let mut vel = Array2::zeros((2, n));
+    match &initial_velocities.distribution
+    {
+        VelocityDistribution::Fixed(v_0) =>
+        {
+            let angles = Array1::random_using(n, Uniform::new(0., 2.*std::f64::consts::PI), rng);
+            vel.slice_mut(s![0,..]).assign(&angles.mapv(|a| v_0*a.cos()));
+            vel.slice_mut(s![1,..]).assign(&angles.mapv(|a| v_0*a.sin()));
+        }
+        VelocityDistribution::MaxwellBoltzmann(_) 
+        | VelocityDistribution::MaxwellBoltzmannPerSpecies(_) =>
+        {
+            let normal = Normal::new(0., 1.).unwrap();
+            for i in 0..n
+            {
+                let t = match &initial_velocities.distribution
+                {
+                    VelocityDistribution::MaxwellBoltzmann(t) => *t,
+                    VelocityDistribution::MaxwellBoltzmannPerSpecies(t) => t[species[i]],
+                    _ => unreachable!(),
+                };
+                assert!(t >= 0., "Negative temperature: {}", t);
+                let sigma = (t / m[i]).sqrt();
+                vel[[0, i]] = sigma * rng.sample(normal);
+                vel[[1, i]] = sigma * rng.sample(normal);
+            }
+        }
+    }
+
+    if initial_velocities.zero_momentum
+    {
+        let m_tot = m.sum();
+        for k in 0..2
+        {
+            let v_cm = vel.row(k).dot(m) / m_tot;
+            vel.row_mut(k).mapv_inplace(|v| v - v_cm);
+        }
+    }
+
+    if let Some(energy) = initial_velocities.total_energy
+    {
+        let e: f64 = (0..n)
+            .map(|i| 0.5 * m[i] * (vel[[0, i]].powi(2) + vel[[1, i]].powi(2)))
+            .sum();
+        assert!(e > 0., "Can not rescale velocities that are all zero.");
+        vel *= (energy / e).sqrt();
+    }
+    vel
+}
+
+
+// Species names are written to tab separated files, so
+// they can not contain tabs, line breaks or quotes.
+pub fn is_valid_species_name(name: &str) -> bool
+{
+    !name.contains(['\t', '\n', '\r', '"'])
+}
+
+
+// How generate_particles fills the box: its borders, the size
+// distribution of each species, the initial velocities, and
+// the strategy for placing the particles.
+#[derive(Clone, Debug)]
+pub struct GenerationOptions
+{
+    pub x_min: f64,
+    pub x_max: f64,
+    pub y_min: f64,
+    pub y_max: f64,
+    pub sizes: Vec<SizeDistribution>,   // One for each species
+    pub velocities: InitialVelocities,
+    pub placement: Placement,
+}
+
+
+impl GenerationOptions
+{
+    // Fixed sizes for the given number of species, the fixed
+    // speed parameters::V_0, and random placement, in a box
+    // from (X_MIN, Y_MIN) to (x_max, y_max).
+    pub fn new(species: usize, x_max: f64, y_max: f64) -> GenerationOptions
+    {
+        GenerationOptions
+        {
+            x_min: parameters::X_MIN,
+            x_max,
+            y_min: parameters::Y_MIN,
+            y_max,
+            sizes: vec![SizeDistribution::Fixed; species],
+            velocities: InitialVelocities::fixed(parameters::V_0),
+            placement: Placement::Random,
+        }
+    }
+}
+
+
 // Fill a box with borders at x_min and x_max with particles
 pub fn generate_particles(
     n_arr: &Array1<usize>, 
-    x_min: f64, 
-    x_max: f64, 
-    y_min: f64, 
-    y_max: f64, 
     r_arr: &Array1<f64>, 
-    m_arr: &Array1<f64>) 
+    m_arr: &Array1<f64>,
+    options: &GenerationOptions,
+    rng: &mut StdRng) 
     -> Particles
 {
-    // Check that the particles can fit within the box
-    // This is a naîve assertion:
-    // Note: If this is only barely true, the 
-    // initialization will take a very long time.
-    
-    let pf = get_packing_fraction(n_arr, r_arr, x_min, y_min, x_max, y_max);
-    assert!(pf < 1., "The particles have a packing fraction of {}, \
-    and they will not fit in the system.", pf);
-
+    let (x_min, x_max, y_min, y_max) = (options.x_min, options.x_max, options.y_min, options.y_max);
+    let sizes = &options.sizes;
+    let initial_velocities = &options.velocities;
+    let placement = &options.placement;
+    assert_eq!(sizes.len(), n_arr.len(), "Every species needs a size distribution.");
     let n = n_arr.sum();
-    let positions: Array2<f64> = stack_new_axis![Axis(0), 
-        Array1::random(n, Uniform::new(x_min, x_max)),
-        Array1::random(n, Uniform::new(y_min, y_max))];
-
-    let angles = Array1::random(n, Uniform::new(0., 2.*std::f64::consts::PI));
-    let mut velocities = stack_new_axis![Axis(0), angles, angles];
-
-    let v_0 = parameters::V_0;
-    velocities.slice_mut(s![0,..]).mapv_inplace(|a| v_0*a.cos());
-    velocities.slice_mut(s![1,..]).mapv_inplace(|a| v_0*a.sin());
-
     let mut radii = Array1::zeros(n);
     let mut masses = Array1::zeros(n);
+    let mut species = Array1::zeros(n);
 
     // Fill radii and masses with values.
+    let mut k = 0;
     for i in 0..n_arr.len()
     {
         for j in 0..n_arr[i]
         {
-            if i != 0
-            {
-                radii[n_arr[i-1] + j] = r_arr[i];
-                masses[n_arr[i-1] + j] = m_arr[i];
-            }
-            else
-            {
-                radii[j] = r_arr[i];
-                masses[j] = m_arr[i];
-            }
-               
+            let (r, m) = sizes[i].sample(r_arr[i], m_arr[i], rng);
+            radii[k + j] = r;
+            masses[k + j] = m;
+            species[k + j] = i;
         }
+        k += n_arr[i];
     }
 
+    // Check that the particles can fit within the box
+    // This is a naîve assertion:
+    // Note: If this is only barely true, the 
+    // initialization will take a very long time.
+    
+    let pf = get_area_fraction(&radii, x_min, y_min, x_max, y_max);
+    assert!(pf < 1., "The particles have a packing fraction of {}, \
+    and they will not fit in the system.", pf);
+
+    let velocities = generate_velocities(&masses, &species, initial_velocities, rng);
+
+
     let mut particles = Particles { 
-        pos: positions,
+        pos: Array2::zeros((2, n)),
         vel: velocities,
         r: radii,
         m: masses,
         collision_count: Array1::zeros(n),
+        species,
+        species_names: (0..n_arr.len()).map(|k| k.to_string()).collect(),
+        sync_time: Array1::zeros(n),
     };
 
-    replace_overlapping_particles(&mut particles, x_min, x_max, y_min, y_max);
+    match placement
+    {
+        Placement::Random =>
+        {
+            particles.pos = stack_new_axis![Axis(0), 
+                Array1::random_using(n, Uniform::new(x_min, x_max), rng),
+                Array1::random_using(n, Uniform::new(y_min, y_max), rng)];
+            replace_overlapping_particles(&mut particles, x_min, x_max, y_min, y_max, rng);
+        }
+        Placement::RandomSequential { max_failures } =>
+            place_random_sequential(&mut particles, x_min, x_max, y_min, y_max, *max_failures, rng),
+        Placement::SquareLattice =>
+            place_on_lattice(&mut particles, x_min, x_max, y_min, y_max, false, rng),
+        Placement::HexagonalLattice =>
+            place_on_lattice(&mut particles, x_min, x_max, y_min, y_max, true, rng),
+        Placement::Compressed { initial_fraction, growth_rate, max_events } =>
+        {
+            // The compression assumes the box starts at the origin.
+            assert!(x_min == 0. && y_min == 0.);
+            let scale = (initial_fraction / pf).sqrt().min(1.);
+            let radii = particles.r.to_owned();
+            let velocities = particles.vel.to_owned();
+            particles.r.mapv_inplace(|r| scale*r);
+            place_random_sequential(&mut particles, x_min, x_max, y_min, y_max, 
+                parameters::MAX_PLACEMENT_FAILURES, rng);
+            if scale < 1.
+            {
+                let (reached, done) = compression::compress(
+                    &mut particles, pf, *growth_rate, *max_events, x_max, y_max);
+                // Particles at their full radii would overlap.
+                assert!(done, "Compression stopped at a packing fraction of {:.4}, \
+                    short of {:.4}. Allow more events.", reached, pf);
+            }
+
+            // The compression ends at the target packing fraction, up to
+            // rounding. Use the exact radii, and the initial velocities.
+            particles.r = radii;
+            particles.vel = velocities;
+        }
+    }
     return particles;
 }
 
 
-fn replace_overlapping_particles(particles: &mut Particles, x_min: f64, x_max: f64, y_min: f64, y_max: f64)
+fn replace_overlapping_particles(
+    particles: &mut Particles, 
+    x_min: f64, 
+    x_max: f64, 
+    y_min: f64, 
+    y_max: f64,
+    rng: &mut StdRng)
 {
-    let mut rng = thread_rng();
-    let mut replaces: i32 = 0;
+    let mut replaces: usize = 0;
     println!("Replacing overlapping particles.");
     for i in 0..particles.get_len()
     {
         simulation::status_bar(i, particles.get_len());
+        let mut failures: usize = 0;
         while !particles.is_within_box(i, x_min, x_max, y_min, y_max) || particles.is_overlapping(i)
         {
             particles.pos[[0,i]] = rng.sample(Uniform::new(x_min, x_max));
             particles.pos[[1,i]] = rng.sample(Uniform::new(y_min, y_max));
 
             replaces += 1;
-            /*
-            if replaces >= 10 * particles.get_len() as i32
+            failures += 1;
+            if failures >= parameters::MAX_PLACEMENT_FAILURES
             {
-                panic!("Replacing particles took too long.\
+                panic!("Replacing particles took too long. \
                 Are you sure there is enough space?");
             }
-            */
         }
     }
     print!(" Done.\n");
@@ -302,6 +673,143 @@ fn replace_overlapping_particles(particles: &mut Particles, x_min: f64, x_max: f
 }
 
 
+// Places particles one by one at random positions, rejecting positions
+// that overlap with particles already placed. The box is divided into
+// cells at least as wide as the largest particle diameter, so only the
+// particles in the nine closest cells need to be checked.
+fn place_random_sequential(
+    particles: &mut Particles, 
+    x_min: f64, 
+    x_max: f64, 
+    y_min: f64, 
+    y_max: f64,
+    max_failures: usize,
+    rng: &mut StdRng)
+{
+    let n = particles.get_len();
+    let r_max = particles.r.fold(0., |a: f64, r| a.max(*r));
+    let cell_size = (2.*r_max).max((x_max - x_min).min(y_max - y_min) * 1e-3);
+    let nx = (((x_max - x_min) / cell_size) as usize).max(1);
+    let ny = (((y_max - y_min) / cell_size) as usize).max(1);
+    let mut cells: Vec<Vec<usize>> = vec![Vec::new(); nx * ny];
+
+    // Large particles are the hardest to fit, so place them first.
+    let mut order: Vec<usize> = (0..n).collect();
+    order.sort_by(|a, b| particles.r[*b].partial_cmp(&particles.r[*a]).unwrap());
+
+    let mut attempts: usize = 0;
+    println!("Placing particles by random sequential addition.");
+    for (k, i) in order.iter().cloned().enumerate()
+    {
+        simulation::status_bar(k, n);
+        let r = particles.r[i];
+        let mut failures: usize = 0;
+        loop
+        {
+            attempts += 1;
+            let x = rng.sample(Uniform::new(x_min + r, x_max - r));
+            let y = rng.sample(Uniform::new(y_min + r, y_max - r));
+            let cx = (((x - x_min) / cell_size) as usize).min(nx - 1);
+            let cy = (((y - y_min) / cell_size) as usize).min(ny - 1);
+
+            let mut overlapping = false;
+            'search: for gx in cx.saturating_sub(1)..(cx + 2).min(nx)
+            {
+                for gy in cy.saturating_sub(1)..(cy + 2).min(ny)
+                {
+                    for j in cells[gx + nx*gy].iter().cloned()
+                    {
+                        let dx = x - particles.pos[[0, j]];
+                        let dy = y - particles.pos[[1, j]];
+                        if dx.powi(2) + dy.powi(2) < (r + particles.r[j]).powi(2)
+                        {
+                            overlapping = true;
+                            break 'search;
+                        }
+                    }
+                }
+            }
+
+            if !overlapping
+            {
+                particles.pos[[0, i]] = x;
+                particles.pos[[1, i]] = y;
+                cells[cx + nx*cy].push(i);
+                break;
+            }
+
+            failures += 1;
+            if failures >= max_failures
+            {
+                panic!("Could not place particle {} of {} after {} attempts. \
+                The packing fraction is probably too high for random placement.",
+                k, n, max_failures);
+            }
+        }
+    }
+    println!(" Done.");
+    println!("Number of positions tried: {}", attempts);
+}
+
+
+// Places particles on a square or hexagonal lattice. The lattice
+// is chosen to have about the same spacing in both directions,
+// and is filled row by row from the bottom of the box.
+fn place_on_lattice(
+    particles: &mut Particles, 
+    x_min: f64, 
+    x_max: f64, 
+    y_min: f64, 
+    y_max: f64,
+    hexagonal: bool,
+    rng: &mut StdRng)
+{
+    let n = particles.get_len();
+    let width = x_max - x_min;
+    let height = y_max - y_min;
+    let r_max = particles.r.fold(0., |a: f64, r| a.max(*r));
+
+    // Rows of a hexagonal lattice are closer than its columns, so
+    // there are more rows and fewer columns than on a square one.
+    let row_ratio = if hexagonal { 3f64.sqrt() / 2. } else { 1. };
+    let columns = ((n as f64 * width * row_ratio / height).sqrt().ceil() as usize).max(1);
+    let rows = n.div_ceil(columns);
+
+    // The square lattice divides the box into equal cells. On the
+    // hexagonal one, every other row is shifted half a column, and
+    // the cells are taken from the box shrunk by a radius on every
+    // side, because half a row spacing from the walls is too little.
+    let (dx, dy, x_0, y_0) = if hexagonal
+    {
+        let dx = (width - 2.*r_max) / columns as f64;
+        let dy = (height - 2.*r_max) / rows as f64;
+        (dx, dy, r_max + 0.25*dx, r_max + 0.5*dy)
+    }
+    else
+    {
+        let dx = width / columns as f64;
+        let dy = height / rows as f64;
+        (dx, dy, 0.5*dx, 0.5*dy)
+    };
+    let nearest = if hexagonal && rows > 1 { dx.min((0.25*dx.powi(2) + dy.powi(2)).sqrt()) } else { dx.min(dy) };
+    // The outermost particles must also be more than a radius from the walls.
+    assert!(nearest > 2.*r_max && x_0 > r_max && y_0 > r_max, "The particles do not fit \
+    on a lattice with {} rows and {} columns.", rows, columns);
+
+    let mut order: Vec<usize> = (0..n).collect();
+    order.shuffle(rng);
+    for (site, i) in order.iter().cloned().enumerate()
+    {
+        let row = site / columns;
+        let column = site % columns;
+        let shift = if hexagonal && row % 2 == 1 { 0.5 } else { 0. };
+        particles.pos[[0, i]] = x_min + x_0 + (column as f64 + shift) * dx;
+        particles.pos[[1, i]] = y_min + y_0 + row as f64 * dy;
+    }
+    println!("Placed particles on a lattice with {} rows and {} columns.", rows, columns);
+}
+
+
 fn wall_collition_time(pos: f64, v: f64, radius: f64, length: f64) -> f64
 {
     // Returns time until particle collides with a wall 
@@ -326,17 +834,18 @@ fn wall_collition_time(pos: f64, v: f64, radius: f64, length: f64) -> f64
 }
 
 
-// Returns time until particle i will collide with particle j.
-fn particle_collision_time(
-    pos: &Array2::<f64>, 
-    vel: &Array2::<f64>,
-    r: &Array1::<f64>,
-    i: usize,
-    j: usize)
-    -> f64
+// Returns time from t until particle i will collide with particle j.
+fn particle_collision_time(p: &Particles, i: usize, j: usize, t: f64) -> f64
 {
-    let (_r_2, d, dvdx, _dx_2, dv_2, _dx) 
-        = calculate_impact_stats(&pos, &vel, &r, i, j);
+    let (xi, yi) = p.get_position_at(i, t);
+    let (xj, yj) = p.get_position_at(j, t);
+    let dx = [xj - xi, yj - yi];
+    let dv = [p.vel[[0, j]] - p.vel[[0, i]], p.vel[[1, j]] - p.vel[[1, i]]];
+
+    let dvdx = dv[0]*dx[0] + dv[1]*dx[1];
+    let dv_2 = dv[0].powi(2) + dv[1].powi(2);
+    let dx_2 = dx[0].powi(2) + dx[1].powi(2);
+    let d = dvdx.powi(2) - dv_2 * (dx_2 - (p.r[i] + p.r[j]).powi(2));
 
     if dvdx < 0. && d > 0.
     {
@@ -374,6 +883,19 @@ pub fn calculate_impact_stats(
 }
 
 
+// Returns the fraction of the box covered by disks with radii r.
+pub fn get_area_fraction(
+    r: &Array1<f64>, 
+    x_min: f64, 
+    y_min: f64, 
+    x_max: f64, 
+    y_max: f64) -> f64
+{
+    let area: f64 = r.iter().map(|r| std::f64::consts::PI * r.powi(2)).sum();
+    area / ((x_max - x_min) * (y_max - y_min))
+}
+
+
 pub fn get_packing_fraction(
     n_arr: &Array1<usize>, r_arr: &Array1<f64>, 
     x_min: f64, 
@@ -387,7 +909,7 @@ pub fn get_packing_fraction(
     {
         area += n_arr[i] as f64 * r_arr[i].powi(2);
     }
-    area *= 2.*std::f64::consts::PI;
+    area *= std::f64::consts::PI;
     return area/box_area;
 }
 
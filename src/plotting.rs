@@ -3,12 +3,66 @@ use gnuplot::{Figure, Color};
 use gnuplot::*;
 use crate::particle;
 use crate::parameters;
+use crate::save_data;
+use crate::render;
 
 //extern crate ndarray;
 use ndarray::s;
 use ndarray::prelude::*;
 
-pub fn plot_positions(particles: &particle::Particles, x_max: f64, y_max: f64)
+// Where a figure is rendered: in an interactive gnuplot window,
+// or to <filename>.png or <filename>.svg in the output directory,
+// with a size in pixels. Files need no display, so they also
+// work on headless machines.
+#[derive(Clone, Debug, PartialEq)]
+pub enum PlotOutput
+{
+    Show,
+    Png { filename: String, width: u32, height: u32 },
+    Svg { filename: String, width: u32, height: u32 },
+}
+
+
+impl PlotOutput
+{
+    pub fn png(filename: &str, width: u32, height: u32) -> PlotOutput
+    {
+        PlotOutput::Png { filename: filename.to_owned(), width, height }
+    }
+
+    pub fn svg(filename: &str, width: u32, height: u32) -> PlotOutput
+    {
+        PlotOutput::Svg { filename: filename.to_owned(), width, height }
+    }
+}
+
+
+// Shows the figure or saves it to file. Failures are only
+// reported, so that a missing gnuplot never stops a run.
+fn render(fig: &mut Figure, output: &PlotOutput)
+{
+    let result = match output
+    {
+        PlotOutput::Show =>
+        {
+            println!("Showing figure...");
+            fig.show().map(drop).map_err(|e| format!("{:?}", e))
+        }
+        PlotOutput::Png { filename, width, height } => save_data::data_path(filename, ".png")
+            .map_err(|e| e.to_string())
+            .and_then(|path| fig.save_to_png(path, *width, *height).map_err(|e| format!("{:?}", e))),
+        PlotOutput::Svg { filename, width, height } => save_data::data_path(filename, ".svg")
+            .map_err(|e| e.to_string())
+            .and_then(|path| fig.save_to_svg(path, *width, *height).map_err(|e| format!("{:?}", e))),
+    };
+    match result
+    {
+        Ok(()) => println!("Figure rendered correctly"),
+        Err(error) => println!("Figure could not be rendered: {}", error),
+    };
+}
+
+pub fn plot_positions(particles: &particle::Particles, x_max: f64, y_max: f64, output: &PlotOutput)
 {
     let mut fig = Figure::new();
     //let mut dv = particles.pos;
@@ -29,19 +83,10 @@ pub fn plot_positions(particles: &particle::Particles, x_max: f64, y_max: f64)
         .set_x_range(Fix(parameters::X_MIN), Fix(x_max))
         .set_y_range(Fix(parameters::Y_MIN), Fix(y_max));
 
-    println!("Showing figure...");
-    match fig.show()
-    {
-        Ok(show) =>
-        {
-            println!("Figure rendered correctly");
-            drop(show);
-        }
-        Err(gnu_error) => println!("Figure could not be rendered: {:?}", gnu_error),
-    };
+    render(&mut fig, output);
 }
 
-pub fn plot_stats(t: ArrayView<f64, Ix1>, y: ArrayView<f64, Ix1>)
+pub fn plot_stats(t: ArrayView<f64, Ix1>, y: ArrayView<f64, Ix1>, output: &PlotOutput)
 {
     let mut fig = Figure::new();
     println!("{:?}", y);
@@ -56,82 +101,108 @@ pub fn plot_stats(t: ArrayView<f64, Ix1>, y: ArrayView<f64, Ix1>)
         .set_y_range(Fix(0.), Fix(6.))
         ;
 
-    match fig.show()
-    {
-        Ok(show) =>
-        {
-            println!("Figure rendered correctly");
-            drop(show);
-        }
-        Err(gnu_error) => println!("Figure could not be rendered: {:?}", gnu_error),
-    };
+    render(&mut fig, output);
 
 }
 
-pub fn plot_energy_single_mass(e: &Array2<f64>)
+// Scales of the axes of a plot.
+#[derive(Clone, Copy, Debug, PartialEq, Default)]
+pub enum Axes
 {
-    let mut fig = Figure::new();
-    fig.axes2d()
-        // Total energy
-        .lines( 
-            e.slice(s![0,..]),
-            e.slice(s![1,..]),
-            &[Color("black"),
-            LineWidth(3.),
-            ])
-        .set_y_range(Fix(0.), Fix(500.))
-        //.set_y_range(Fix(0.), Fix(60.))
-        ;
+    #[default]
+    Linear,
+    LogY,
+    LogLog,
+}
+
+
+// Options of plot_energy. normalized divides every energy by its
+// initial value, and cooling_fit overlays Haff's law, fitted
+// to the total energy.
+#[derive(Clone, Copy, Debug, PartialEq, Default)]
+pub struct EnergyPlot
+{
+    pub axes: Axes,
+    pub normalized: bool,
+    pub cooling_fit: bool,
+}
+
 
-    match fig.show()
+// Plots the total energy and the energy of every species
+// against time. e is laid out like the system_data returned
+// by simulation::evolve_system, and species_names labels its rows.
+pub fn plot_energy(e: &Array2<f64>, species_names: &[String], options: &EnergyPlot, output: &PlotOutput)
+{
+    assert_eq!(e.nrows(), species_names.len() + 2, "Every species needs a name.");
+    let t = e.row(0);
+
+    let mut rows = vec![("total".to_owned(), e.row(1).to_owned())];
+    for (k, name) in species_names.iter().enumerate()
     {
-        Ok(show) =>
+        rows.push((name.clone(), e.row(k + 2).to_owned()));
+    }
+    if options.normalized
+    {
+        for (_, row) in rows.iter_mut()
         {
-            println!("Figure rendered correctly");
-            drop(show);
+            let e_0 = row[0];
+            if e_0 > 0.
+            {
+                *row /= e_0;
+            }
         }
-        Err(gnu_error) => println!("Figure could not be rendered: {:?}", gnu_error),
-    };
-}
- 
-pub fn plot_energy_two_masses(e: &Array2<f64>)
-{
+    }
+
     let mut fig = Figure::new();
-    fig.axes2d()
-        // Total energy
-        .lines( 
-            e.slice(s![0,..]),
-            e.slice(s![1,..]),
-            &[Color("black"),
-            LineWidth(3.),
-            ])
-        // Energy of first particle mass
-        .lines(
-            e.slice(s![0,..]),
-            e.slice(s![2,..]),
-            &[Color("red"),
-            LineWidth(3.),
-            ])
-        // Energy of second particle mass
-        // This will give an out of range error if all particles are the same.
-         .lines(
-            e.slice(s![0,..]),
-            e.slice(s![3,..]),
-            &[Color("blue"),
-            LineWidth(3.),
-            ])       
-        //.set_x_range(Fix(0.), Fix(2.))
-        //.set_y_range(Fix(0.), Fix(60.))
-        ;
+    let axes = fig.axes2d();
+    axes.set_x_label("time", &[])
+        .set_y_label(if options.normalized { "E / E_0" } else { "kinetic energy" }, &[]);
+    if options.axes != Axes::Linear
+    {
+        axes.set_y_log(Some(10.));
+    }
+    if options.axes == Axes::LogLog
+    {
+        axes.set_x_log(Some(10.));
+    }
+
+    let colors: Vec<String> = render::SPECIES_COLORS.iter()
+        .map(|c| format!("#{:02x}{:02x}{:02x}", c[0], c[1], c[2])).collect();
+    for (k, (name, row)) in rows.iter().enumerate()
+    {
+        let color = if k == 0 { "black" } else { &colors[(k - 1) % colors.len()] };
+        axes.lines(t, row, &[Caption(name), Color(color), LineWidth(3.)]);
+    }
 
-    match fig.show()
+    if options.cooling_fit
     {
-        Ok(show) =>
+        let tau = fit_haff_law(t, rows[0].1.view());
+        let fit = t.mapv(|time| rows[0].1[0] / (1. + (time - t[0]) / tau).powi(2));
+        axes.lines(t, &fit, &[Caption(&format!("Haff's law, tau = {:.3e}", tau)),
+            Color("gray"), LineWidth(2.), LineStyle(Dash)]);
+    }
+
+    render(&mut fig, output);
+}
+
+
+// Fits Haff's law, E(t) = E_0 / (1 + (t - t_0) / tau)^2, to the
+// energy of a cooling gas, and returns tau. Linearized, the law
+// reads sqrt(E_0 / E) = 1 + (t - t_0) / tau, so 1 / tau is found
+// by least squares through (t_0, 1). Returns infinity if the
+// energy does not decrease.
+pub fn fit_haff_law(t: ArrayView1<f64>, e: ArrayView1<f64>) -> f64
+{
+    let mut numerator = 0.;
+    let mut denominator = 0.;
+    for (time, energy) in t.iter().zip(e.iter())
+    {
+        if *energy > 0.
         {
-            println!("Figure rendered correctly");
-            drop(show);
+            let dt = time - t[0];
+            numerator += dt * ((e[0] / energy).sqrt() - 1.);
+            denominator += dt.powi(2);
         }
-        Err(gnu_error) => println!("Figure could not be rendered: {:?}", gnu_error),
-    };
+    }
+    if numerator > 0. { denominator / numerator } else { f64::INFINITY }
 }
- 
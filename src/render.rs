@@ -0,0 +1,282 @@
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::Path;
+
+use crate::particle;
+use crate::save_data;
+use crate::trajectory::FrameWriter;
+
+
+// What the color of a particle shows. Speeds and collision
+// counts are mapped onto a color scale from 0 to the given
+// maximum, which should be the same for every frame of an
+// animation. Larger values get the color of the maximum.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ColorBy
+{
+    Species,
+    Speed(f64),
+    CollisionCount(u32),
+}
+
+
+// The image is drawn with a palette, which is shared by PNG,
+// SVG and GIF output. Index 0 is the background, then come
+// the species colors and the color scale. The species colors
+// are also used by the plots in plotting.rs.
+const BACKGROUND: [u8; 3] = [255, 255, 255];
+pub const SPECIES_COLORS: [[u8; 3]; 8] = [
+    [31, 119, 180], [214, 39, 40], [44, 160, 44], [255, 127, 14],
+    [148, 103, 189], [140, 86, 75], [227, 119, 194], [127, 127, 127]];
+const SCALE_COLORS: [[u8; 3]; 5] = [
+    [68, 1, 84], [59, 82, 139], [33, 145, 140], [94, 201, 98], [253, 231, 37]];
+const SCALE_STEPS: usize = 128;
+
+
+fn make_palette() -> Vec<[u8; 3]>
+{
+    let mut palette = vec![BACKGROUND];
+    palette.extend(SPECIES_COLORS.iter());
+    for k in 0..SCALE_STEPS
+    {
+        // Interpolate linearly between the colors of the scale.
+        let x = k as f64 / (SCALE_STEPS - 1) as f64 * (SCALE_COLORS.len() - 1) as f64;
+        let j = (x as usize).min(SCALE_COLORS.len() - 2);
+        let w = x - j as f64;
+        let mut color = [0; 3];
+        for (c, value) in color.iter_mut().enumerate()
+        {
+            *value = ((1. - w) * SCALE_COLORS[j][c] as f64 + w * SCALE_COLORS[j + 1][c] as f64)
+                .round() as u8;
+        }
+        palette.push(color);
+    }
+    palette
+}
+
+
+// Returns the index in the palette of the color of particle i.
+fn color_index(p: &particle::Particles, i: usize, color_by: ColorBy) -> u8
+{
+    let fraction = match color_by
+    {
+        ColorBy::Species => return 1 + (p.species[i] % SPECIES_COLORS.len()) as u8,
+        ColorBy::Speed(v_max) => p.get_speed(i) / v_max,
+        ColorBy::CollisionCount(max) => p.collision_count[i] as f64 / max as f64,
+    };
+    let step = (fraction.clamp(0., 1.) * (SCALE_STEPS - 1) as f64).round() as usize;
+    (1 + SPECIES_COLORS.len() + step) as u8
+}
+
+
+// An image of the particles, as indices into the palette.
+// Every pixel is one row-major byte.
+struct Image
+{
+    width: u32,
+    height: u32,
+    pixels: Vec<u8>,
+}
+
+
+// Draws every particle as a disk of its true radius. The box is
+// width pixels wide, and the height follows from its aspect ratio.
+// Particles smaller than a pixel still cover the pixel they are in.
+fn rasterize(p: &particle::Particles, x_max: f64, y_max: f64, width: u32, color_by: ColorBy)
+    -> Image
+{
+    let scale = width as f64 / x_max;
+    let height = ((y_max * scale).round() as u32).max(1);
+    let mut pixels = vec![0; (width * height) as usize];
+
+    for i in 0..p.get_len()
+    {
+        let color = color_index(p, i, color_by);
+        // Position and radius in pixels, with y pointing down.
+        let x = p.pos[[0, i]] * scale;
+        let y = (y_max - p.pos[[1, i]]) * scale;
+        let r = p.r[i] * scale;
+
+        let columns = (x - r).floor().max(0.) as u32..((x + r).ceil() as u32).min(width);
+        let rows = (y - r).floor().max(0.) as u32..((y + r).ceil() as u32).min(height);
+        let mut covered = false;
+        for row in rows
+        {
+            for column in columns.clone()
+            {
+                let dx = column as f64 + 0.5 - x;
+                let dy = row as f64 + 0.5 - y;
+                if dx.powi(2) + dy.powi(2) <= r.powi(2)
+                {
+                    pixels[(row * width + column) as usize] = color;
+                    covered = true;
+                }
+            }
+        }
+        if !covered && x >= 0. && y >= 0. && (x as u32) < width && (y as u32) < height
+        {
+            pixels[(y as u32 * width + x as u32) as usize] = color;
+        }
+    }
+    Image { width, height, pixels }
+}
+
+
+fn write_png(image: &Image, path: &Path) -> save_data::SaveResult<()>
+{
+    let f = BufWriter::new(File::create(path)?);
+    let mut encoder = png::Encoder::new(f, image.width, image.height);
+    encoder.set_color(png::ColorType::Indexed);
+    encoder.set_depth(png::BitDepth::Eight);
+    encoder.set_palette(make_palette().concat());
+    let mut writer = encoder.write_header()?;
+    writer.write_image_data(&image.pixels)?;
+    writer.finish()?;
+    Ok(())
+}
+
+
+// Draws the particles to <filename>.png, width pixels wide.
+pub fn render_png(
+    p: &particle::Particles,
+    x_max: f64,
+    y_max: f64,
+    color_by: ColorBy,
+    width: u32,
+    filename: &str)
+    -> save_data::SaveResult<()>
+{
+    let path = save_data::data_path(filename, ".png")?;
+    write_png(&rasterize(p, x_max, y_max, width, color_by), &path)?;
+    println!("Image saved succesfully to file:\n{}", path.display());
+    Ok(())
+}
+
+
+// Draws the particles to <filename>.svg, width pixels wide.
+// The disks are drawn in the units of the simulation, so
+// the image can be scaled without losing detail.
+pub fn render_svg(
+    p: &particle::Particles,
+    x_max: f64,
+    y_max: f64,
+    color_by: ColorBy,
+    width: u32,
+    filename: &str)
+    -> save_data::SaveResult<()>
+{
+    let path = save_data::data_path(filename, ".svg")?;
+    let mut f = BufWriter::new(File::create(&path)?);
+    let palette = make_palette();
+    let hex = |color: [u8; 3]| format!("#{:02x}{:02x}{:02x}", color[0], color[1], color[2]);
+
+    let height = (width as f64 * y_max / x_max).round();
+    writeln!(f, "<svg xmlns=\"http://www.w3.org/2000/svg\" width=\"{}\" height=\"{}\" \
+        viewBox=\"0 0 {:?} {:?}\">", width, height, x_max, y_max)?;
+    writeln!(f, "<rect width=\"{:?}\" height=\"{:?}\" fill=\"{}\"/>", x_max, y_max, hex(BACKGROUND))?;
+    for i in 0..p.get_len()
+    {
+        writeln!(f, "<circle cx=\"{:?}\" cy=\"{:?}\" r=\"{:?}\" fill=\"{}\"/>",
+            p.pos[[0, i]], y_max - p.pos[[1, i]], p.r[i],
+            hex(palette[color_index(p, i, color_by) as usize]))?;
+    }
+    writeln!(f, "</svg>")?;
+    f.flush()?;
+    println!("Image saved succesfully to file:\n{}", path.display());
+    Ok(())
+}
+
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum AnimationFormat
+{
+    PngFrames,      // <filename>_frame_00000.png, <filename>_frame_00001.png, ...
+    Gif(u16),       // <filename>.gif, with the delay between frames in 1/100 s
+}
+
+
+// Renders a frame every `every` events of a run, either as a
+// sequence of PNG images or as an animated GIF.
+pub struct Animation
+{
+    filename: String,
+    format: AnimationFormat,
+    every: usize,
+    width: u32,
+    color_by: ColorBy,
+    frames: usize,
+    gif: Option<gif::Encoder<BufWriter<File>>>,
+}
+
+
+impl Animation
+{
+    pub fn new(filename: &str, format: AnimationFormat, every: usize, width: u32, color_by: ColorBy)
+        -> Animation
+    {
+        assert!(every > 0, "Frames must be written at least every event.");
+        Animation
+        {
+            filename: filename.to_owned(),
+            format,
+            every,
+            width,
+            color_by,
+            frames: 0,
+            gif: None,
+        }
+    }
+}
+
+
+impl FrameWriter for Animation
+{
+    fn get_interval(&self) -> usize
+    {
+        self.every
+    }
+
+    fn write_frame(&mut self, p: &particle::Particles, _event: usize, _t: f64, x_max: f64, y_max: f64)
+        -> save_data::SaveResult<()>
+    {
+        let image = rasterize(p, x_max, y_max, self.width, self.color_by);
+        match self.format
+        {
+            AnimationFormat::PngFrames =>
+            {
+                let path = save_data::data_path(
+                    &format!("{}_frame_{:05}", self.filename, self.frames), ".png")?;
+                write_png(&image, &path)?;
+            }
+            AnimationFormat::Gif(delay) =>
+            {
+                // The encoder is made with the first frame, when the size is known.
+                if self.gif.is_none()
+                {
+                    let path = save_data::data_path(&self.filename, ".gif")?;
+                    let f = BufWriter::new(File::create(path)?);
+                    let mut encoder = gif::Encoder::new(
+                        f, image.width as u16, image.height as u16, &make_palette().concat())?;
+                    encoder.set_repeat(gif::Repeat::Infinite)?;
+                    self.gif = Some(encoder);
+                }
+                let mut frame = gif::Frame::from_indexed_pixels(
+                    image.width as u16, image.height as u16, image.pixels, None);
+                frame.delay = delay;
+                self.gif.as_mut().unwrap().write_frame(&frame)?;
+            }
+        }
+        self.frames += 1;
+        Ok(())
+    }
+
+    fn finish(&mut self) -> save_data::SaveResult<()>
+    {
+        if let Some(encoder) = self.gif.take()
+        {
+            encoder.into_inner()?.flush()?;
+        }
+        println!("{} animation frames saved succesfully to {}.", self.frames, self.filename);
+        Ok(())
+    }
+}
@@ -2,15 +2,24 @@ use ndarray::prelude::*;
 
 use std::vec::Vec;
 use std::error::Error;
-use std::fs::File;
-use std::io::{Write};
-use std::path::Path;
+use std::fs::{self, File};
+use std::io::{self, Write};
+use std::path::PathBuf;
+use std::sync::RwLock;
 use std::env;
 
 use crate::particle;
+use crate::parameters;
+use crate::scenario::Scenario;
+
+// The error returned by everything that writes data. It can be
+// sent between threads, so runs in parallel can return it.
+pub type SaveResult<T> = Result<T, Box<dyn Error + Send + Sync>>;
+
+static OUTPUT_DIR: RwLock<Option<PathBuf>> = RwLock::new(None);
 
 pub fn particles_to_file(p: &particle::Particles, filename: &str) 
--> Result<(), Box<dyn Error>>
+-> SaveResult<()>
 {
     let data: Vec<Vec<f64>> = vec![
         p.pos.row(0).to_vec(),
@@ -19,19 +28,22 @@ pub fn particles_to_file(p: &particle::Particles, filename: &str)
         p.vel.row(1).to_vec(),
         p.r.to_vec(),
         p.m.to_vec(),
-        p.collision_count.map(|a| *a as f64).to_vec()];
+        p.collision_count.map(|a| *a as f64).to_vec(),
+        p.species.map(|a| *a as f64).to_vec()];
 
-    let wd = env::current_dir().unwrap().display().to_string();
-    let path_name = wd + &"/../data/".to_owned() + &filename.to_owned() + "_particles.csv";
-    let path = Path::new(&path_name);
-    let mut f = File::create(&path).expect("Could not open file.");
+    if let Some(name) = p.species_names.iter().find(|name| !particle::is_valid_species_name(name))
+    {
+        return Err(format!("The species name {:?} can not be saved to file.", name).into());
+    }
+    let path = data_path(filename, "_particles.csv")?;
+    let mut f = File::create(&path)?;
 
-    write!(f, "x\ty\tv_x\tv_y\tradius\tmass\tcount\tenergy\n")?;
+    write!(f, "x\ty\tv_x\tv_y\tradius\tmass\tcount\tspecies\tenergy\tname\n")?;
     for i in 0..p.get_len()
     {
-        for j in 0..8
+        for j in 0..9
         {
-            if j < 7
+            if j < 8
             {
                 write!(f, "{:?}\t", data[j][i])?;
             }
@@ -40,23 +52,25 @@ pub fn particles_to_file(p: &particle::Particles, filename: &str)
                 write!(f, "{:?}\t", p.get_kinetic_energy(i))?;
             }
         }
-        write!(f, "\n")?;
+        write!(f, "{}\n", p.species_names[p.species[i]])?;
     }
-    println!("Data saved succesfully to file:\n{}", path_name);
+    println!("Data saved succesfully to file:\n{}", path.display());
     Ok(())
 }
 
-pub fn speed_to_file(data: &Array2<f64>, filename: &str)
--> Result<(), Box<dyn Error>>
+// The first row of data holds the initial speeds, the
+// others the final speeds of each species, named in species_names.
+pub fn speed_to_file(data: &Array2<f64>, species_names: &[String], filename: &str)
+-> SaveResult<()>
 {
-    let wd = env::current_dir().unwrap().display().to_string();
-    let path_name = wd + &"/../data/".to_owned() + &filename.to_owned() + "_speeds.csv";
-    let path = Path::new(&path_name);
-    let mut f = File::create(&path).expect("Could not open file.");
+    assert_eq!(data.nrows(), species_names.len() + 1, "Every species needs a name.");
+    let path = data_path(filename, "_speeds.csv")?;
+    let mut f = File::create(&path)?;
 
-    for i in 0..data.nrows()
+    write!(f, "v_init\t")?;
+    for name in species_names.iter()
     {
-        write!(f, "v_{:?}\t", i)?; 
+        write!(f, "v_{}\t", name)?; 
     }
     write!(f, "\n")?;
 
@@ -68,22 +82,21 @@ pub fn speed_to_file(data: &Array2<f64>, filename: &str)
         }
         write!(f, "\n")?;
     }
-    println!("Data saved succesfully to file:\n{}", path_name);
+    println!("Data saved succesfully to file:\n{}", path.display());
     Ok(())
 }
 
-pub fn energy_to_file(data: &Array2<f64>, filename: &str)
--> Result<(), Box<dyn Error>>
+pub fn energy_to_file(data: &Array2<f64>, species_names: &[String], filename: &str)
+-> SaveResult<()>
 {
-    let wd = env::current_dir().unwrap().display().to_string();
-    let path_name = wd + &"/../data/".to_owned() + &filename.to_owned() + "_energy.csv";
-    let path = Path::new(&path_name);
-    let mut f = File::create(&path).expect("Could not open file.");
+    assert_eq!(data.nrows(), species_names.len() + 2, "Every species needs a name.");
+    let path = data_path(filename, "_energy.csv")?;
+    let mut f = File::create(&path)?;
 
     write!(f, "time\te_tot")?;
-    for i in 0..data.nrows()-1
+    for name in species_names.iter()
     {
-        write!(f, "\te_{:?}", i)?; 
+        write!(f, "\te_{}", name)?; 
     }
     write!(f, "\n")?;
 
@@ -95,17 +108,15 @@ pub fn energy_to_file(data: &Array2<f64>, filename: &str)
         }
         write!(f, "\n")?;
     }
-    println!("Data saved succesfully to file:\n{}", path_name);
+    println!("Data saved succesfully to file:\n{}", path.display());
     Ok(())
 }
 
 pub fn crater_size_to_file(crater_size: &Array2<f64>, filename: &str)
--> Result<(), Box<dyn Error>>
+-> SaveResult<()>
 {
-    let wd = env::current_dir().unwrap().display().to_string();
-    let path_name = wd + &"/../data/".to_owned() + &filename.to_owned() + "_crater_size.csv";
-    let path = Path::new(&path_name);
-    let mut f = File::create(&path).expect("Could not open file.");
+    let path = data_path(filename, "_crater_size.csv")?;
+    let mut f = File::create(&path)?;
 
     write!(f, "xi\tsize\n")?;
     for i in 0..crater_size.ncols()
@@ -116,41 +127,234 @@ pub fn crater_size_to_file(crater_size: &Array2<f64>, filename: &str)
         }
         write!(f, "\n")?;
     }
-    println!("Data saved succesfully to file:\n{}", path_name);
+    println!("Data saved succesfully to file:\n{}", path.display());
+    Ok(())
+}
+
+// Writes the number of particles and their kinetic energy in
+// each of n_bins size bins, for polydisperse systems.
+pub fn size_bins_to_file(p: &particle::Particles, n_bins: usize, filename: &str)
+-> SaveResult<()>
+{
+    let edges = p.get_size_bins(n_bins);
+    let count = p.get_count_per_size_bin(&edges);
+    let energy = p.get_kinetic_energy_per_size_bin(&edges);
+
+    let mut data = Array2::zeros((4, n_bins));
+    for b in 0..n_bins
+    {
+        data[[0, b]] = 0.5 * (edges[b] + edges[b + 1]);
+        data[[1, b]] = count[b] as f64;
+        data[[2, b]] = energy[b];
+        data[[3, b]] = if count[b] > 0 { energy[b] / count[b] as f64 } else { f64::NAN };
+    }
+    let names: Vec<String> = ["radius", "count", "energy", "mean_energy"]
+        .iter().map(|name| name.to_string()).collect();
+    table_to_file(&names, &data, filename, "size_bins")
+}
+
+// Writes every parameter of a scenario, including the seed
+// of the random number generator, so that the run can be repeated.
+pub fn scenario_to_file(s: &Scenario, filename: &str)
+-> SaveResult<()>
+{
+    let path = data_path(filename, "_scenario.csv")?;
+    let mut f = File::create(&path)?;
+
+    writeln!(f, "parameter\tvalue")?;
+    writeln!(f, "name\t{}", s.name)?;
+    writeln!(f, "seed\t{}", s.seed)?;
+    for name in s.get_parameter_names()
+    {
+        writeln!(f, "{}\t{:?}", name, s.get_parameter(&name))?;
+    }
+    println!("Data saved succesfully to file:\n{}", path.display());
+    Ok(())
+}
+
+// Writes the metrics of every realization of an ensemble, one
+// realization per line, after the seed that it was run with.
+pub fn realizations_to_file(seeds: &[u64], names: &[String], metrics: &Array2<f64>, filename: &str)
+-> SaveResult<()>
+{
+    assert_eq!(names.len(), metrics.nrows(), "Every metric needs a name.");
+    assert_eq!(seeds.len(), metrics.ncols(), "Every realization needs a seed.");
+    let path = data_path(filename, "_ensemble_realizations.csv")?;
+    let mut f = File::create(&path)?;
+
+    writeln!(f, "seed\t{}", names.join("\t"))?;
+    for (k, seed) in seeds.iter().enumerate()
+    {
+        write!(f, "{}\t", seed)?;
+        for j in 0..metrics.nrows()
+        {
+            write!(f, "{:?}\t", metrics[[j, k]])?;
+        }
+        writeln!(f)?;
+    }
+    println!("Data saved succesfully to file:\n{}", path.display());
+    Ok(())
+}
+
+// Writes the summary of a parameter sweep.
+pub fn summary_to_file(names: &[String], data: &Array2<f64>, filename: &str)
+-> SaveResult<()>
+{
+    table_to_file(names, data, filename, "summary")
+}
+
+// Writes a table with one named column per row of data,
+// to a file called <filename>_<kind>.csv.
+pub fn table_to_file(names: &[String], data: &Array2<f64>, filename: &str, kind: &str)
+-> SaveResult<()>
+{
+    assert_eq!(names.len(), data.nrows(), "Every row of data needs a name.");
+    let path = data_path(filename, &format!("_{}.csv", kind))?;
+    let mut f = File::create(&path)?;
+
+    writeln!(f, "{}", names.join("\t"))?;
+    for i in 0..data.ncols()
+    {
+        for j in 0..data.nrows()
+        {
+            write!(f, "{:?}\t", data[[j, i]])?;
+        }
+        writeln!(f)?;
+    }
+    println!("Data saved succesfully to file:\n{}", path.display());
     Ok(())
 }
 
+// Sets the directory all data is written to. Unless it
+// is set, the directory given by the environment variable
+// GRANULAR_GAS_DATA_DIR, or else parameters::DATA_DIR, is used.
+pub fn set_output_dir(dir: &str)
+{
+    *OUTPUT_DIR.write().unwrap() = Some(PathBuf::from(dir));
+}
 
-/*
-pub fn file_to_particles(filename: &str, n: usize) -> Result<Particles, Box<dyn Error>>
+pub fn get_output_dir() -> PathBuf
 {
-    let f_pos = File::open(filename.to_owned() + "_pos")?;
-    let mut reader = ReaderBuilder::new().has_headers(false).from_reader(f);
-    let pos_read: Array2<f64> = reader.deserialize_array2((2, n))?;
-
-    let f_vel = File::open(filename.to_owned() + "_vel")?;
-    let mut reader = ReaderBuilder::new().has_headers(false).from_reader(f);
-    let vel_read: Array2<f64> = reader.deserialize_array2((2, n))?;
-
-    /*
-    let f_r = File::open(filename.to_owned() + "_r")?;
-    let mut reader = ReaderBuilder::new().has_headers(false).from_reader(f);
-    let r_read: Array1<f64> = reader.deserialize_array1(n)?;
-
-    let f_m = File::open(filename.to_owned() + "_m")?;
-    let mut reader = ReaderBuilder::new().has_headers(false).from_reader(f);
-    let m_read: Array1<f64> = reader.deserialize_array1(n)?;
-
-    let f_cc = File::open(filename.to_owned() + "_cc")?;
-    let mut reader = ReaderBuilder::new().has_headers(false).from_reader(f);
-    let cc_read: Array1<u16> = reader.deserialize_array1(n)?;
-    */
-
-    Ok(Particles{ 
-        pos: pos_read, 
-        vel: vel_read, 
-        r: r_read, 
-        m: m_read, 
-        collision_count: cc_read })
+    if let Some(dir) = OUTPUT_DIR.read().unwrap().as_ref()
+    {
+        return dir.clone();
+    }
+    match env::var("GRANULAR_GAS_DATA_DIR")
+    {
+        Ok(dir) => PathBuf::from(dir),
+        Err(_) => env::current_dir().unwrap_or_default().join(parameters::DATA_DIR),
+    }
+}
+
+// Returns the path of <filename><suffix> in the output directory.
+// filename may contain subdirectories, which are created
+// along with the output directory if they do not exist.
+pub fn data_path(filename: &str, suffix: &str) -> SaveResult<PathBuf>
+{
+    let path = get_output_dir().join(filename.to_owned() + suffix);
+    if let Some(dir) = path.parent()
+    {
+        fs::create_dir_all(dir)?;
+    }
+    Ok(path)
+}
+
+// Creates a new subdirectory of the output directory for a run
+// called name, and returns its name. If a directory called name
+// already exists, name_1, name_2, ... is used instead, so that
+// earlier runs are never overwritten.
+pub fn create_run_dir(name: &str) -> SaveResult<String>
+{
+    let output_dir = get_output_dir();
+    fs::create_dir_all(&output_dir)?;
+    let mut k = 0;
+    loop
+    {
+        let dirname = if k == 0 { name.to_owned() } else { format!("{}_{}", name, k) };
+        match fs::create_dir(output_dir.join(&dirname))
+        {
+            Ok(()) => return Ok(dirname),
+            Err(e) if e.kind() == io::ErrorKind::AlreadyExists => k += 1,
+            Err(e) => return Err(e.into()),
+        }
+    }
+}
+
+// Reads particles written by particles_to_file. Files without
+// species names, written by older versions, name the species
+// by index.
+pub fn file_to_particles(filename: &str) -> SaveResult<particle::Particles>
+{
+    let path = data_path(filename, "_particles.csv")?;
+    let mut reader = csv::ReaderBuilder::new()
+        .delimiter(b'\t')
+        .flexible(true)
+        .from_path(&path)?;
+
+    let names = ["x", "y", "v_x", "v_y", "radius", "mass", "count", "species"];
+    let headers = reader.headers()?.clone();
+    let mut columns = Vec::with_capacity(names.len());
+    for name in names.iter()
+    {
+        match headers.iter().position(|h| h == *name)
+        {
+            Some(j) => columns.push(j),
+            None => return Err(format!("{} has no column called {}.", path.display(), name).into()),
+        }
+    }
+
+    let name_column = headers.iter().position(|h| h == "name");
+
+    let mut values: Vec<Vec<f64>> = vec![Vec::new(); names.len()];
+    let mut species_names: Vec<Option<String>> = Vec::new();
+    for record in reader.records()
+    {
+        let record = record?;
+        if let Some(j) = name_column
+        {
+            let k = record.get(columns[7])
+                .ok_or(format!("A line of {} is too short.", path.display()))?
+                .trim().parse::<f64>()? as usize;
+            let name = record.get(j)
+                .ok_or(format!("A line of {} is too short.", path.display()))?;
+            if species_names.len() <= k
+            {
+                species_names.resize(k + 1, None);
+            }
+            species_names[k] = Some(name.to_owned());
+        }
+        for (k, j) in columns.iter().enumerate()
+        {
+            let field = record.get(*j)
+                .ok_or(format!("A line of {} is too short.", path.display()))?;
+            values[k].push(field.trim().parse::<f64>()?);
+        }
+    }
+
+    let n = values[0].len();
+    let species: Array1<usize> = values[7].iter().map(|k| *k as usize).collect();
+    let n_species = species.iter().max().map_or(0, |k| k + 1);
+    let mut pos = Array2::zeros((2, n));
+    let mut vel = Array2::zeros((2, n));
+    for i in 0..n
+    {
+        pos[[0, i]] = values[0][i];
+        pos[[1, i]] = values[1][i];
+        vel[[0, i]] = values[2][i];
+        vel[[1, i]] = values[3][i];
+    }
+    println!("Read {} particles from file:\n{}", n, path.display());
+    Ok(particle::Particles
+    {
+        pos,
+        vel,
+        r: Array1::from(values[4].clone()),
+        m: Array1::from(values[5].clone()),
+        collision_count: values[6].iter().map(|c| *c as u32).collect(),
+        species,
+        species_names: (0..n_species)
+            .map(|k| species_names.get(k).cloned().flatten().unwrap_or_else(|| k.to_string()))
+            .collect(),
+        sync_time: Array1::zeros(n),
+    })
 }
-*/
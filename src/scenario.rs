@@ -0,0 +1,280 @@
+use ndarray::prelude::*;
+
+use crate::particle;
+use crate::parameters;
+use crate::simulation;
+use crate::indexed_queue;
+use crate::trajectory;
+use crate::event_log;
+use crate::dashboard;
+use crate::observer::{self, Observer};
+use crate::stop;
+use crate::save_data;
+
+
+// A Scenario collects every parameter needed to set up
+// and run one simulation. Parameters can be read and
+// written by name, which is what the sweeps use.
+#[derive(Clone)]
+pub struct Scenario
+{
+    pub name: String,
+    pub species_names: Vec<String>,
+    pub n: Array1<usize>,   // Number of particles of each species
+    pub r: Array1<f64>,     // Radius of each species
+    pub m: Array1<f64>,     // Mass of each species
+    pub xi: f64,
+    pub sizes: Vec<particle::SizeDistribution>,   // One for each species
+    pub velocities: particle::InitialVelocities,
+    pub x_max: f64,
+    pub y_max: f64,
+    pub number_of_events: usize,
+    pub energy_cutoff_fraction: f64,
+    // Stops the run early, together with number_of_events and
+    // energy_cutoff_fraction. None stops only at those.
+    pub stop: Option<stop::StopCondition>,
+    pub tc: bool,
+    pub seed: u64,
+    pub placement: particle::Placement,
+    // Queue the upcoming collisions are kept in.
+    pub queue: indexed_queue::QueueKind,
+    // Format of the trajectory written by simulate, and the
+    // number of events between frames. None writes nothing.
+    pub trajectory: Option<(trajectory::TrajectoryFormat, usize)>,
+    // Format of the log of every collision. None writes nothing.
+    pub event_log: Option<event_log::EventLogFormat>,
+    // Number of events between energy samples, which are written
+    // to <name>_energy.csv while the simulation runs. None writes nothing.
+    pub energy_stream: Option<usize>,
+    // Number of events between refreshes of the terminal
+    // dashboard, and its map style. None shows the status bar.
+    pub dashboard: Option<(usize, dashboard::MapStyle)>,
+}
+
+
+impl Scenario
+{
+    pub fn new(name: &str, n: Array1<usize>, r: Array1<f64>, m: Array1<f64>)
+        -> Scenario
+    {
+        assert_eq!(n.len(), r.len(), "Every species needs a radius.");
+        assert_eq!(n.len(), m.len(), "Every species needs a mass.");
+        let sizes = vec![particle::SizeDistribution::Fixed; n.len()];
+        Scenario
+        {
+            name: name.to_owned(),
+            species_names: (0..n.len()).map(|k| k.to_string()).collect(),
+            n,
+            r,
+            m,
+            xi: 1.0,
+            sizes,
+            velocities: particle::InitialVelocities::fixed(parameters::V_0),
+            x_max: 1.0,
+            y_max: 1.0,
+            number_of_events: parameters::NUMBER_OF_COLLISIONS,
+            energy_cutoff_fraction: 0.0,
+            stop: None,
+            tc: false,
+            seed: parameters::SEED,
+            placement: particle::Placement::Random,
+            queue: indexed_queue::QueueKind::Heap,
+            trajectory: None,
+            event_log: None,
+            energy_stream: None,
+            dashboard: None,
+        }
+    }
+
+
+    pub fn set_species_names(&mut self, names: &[&str]) -> &mut Scenario
+    {
+        assert_eq!(names.len(), self.n.len(), "Every species needs a name.");
+        for name in names.iter()
+        {
+            assert!(particle::is_valid_species_name(name),
+                "The species name {:?} can not be saved to file.", name);
+        }
+        self.species_names = names.iter().map(|name| name.to_string()).collect();
+        self
+    }
+
+
+    // Sets a parameter by name. Species parameters are
+    // given as n_<k>, r_<k> or m_<k>, where k is the
+    // index of the species. Setting v_0 or temperature
+    // also sets the velocity distribution. The seed is not
+    // a parameter, since not every u64 is exact as an f64.
+    pub fn set_parameter(&mut self, name: &str, value: f64)
+    {
+        match name
+        {
+            "xi" => self.xi = value,
+            "v_0" => self.velocities.distribution 
+                = particle::VelocityDistribution::Fixed(value),
+            "temperature" => self.velocities.distribution 
+                = particle::VelocityDistribution::MaxwellBoltzmann(value),
+            "total_energy" => self.velocities.total_energy 
+                = if value.is_nan() { None } else { Some(value) },
+            "x_max" => self.x_max = value,
+            "y_max" => self.y_max = value,
+            "events" => self.number_of_events = value as usize,
+            "energy_cutoff" => self.energy_cutoff_fraction = value,
+            "tc" => self.tc = value != 0.,
+            _ =>
+            {
+                let (key, k) = self.split_species_parameter(name);
+                match key
+                {
+                    "n" => self.n[k] = value as usize,
+                    "r" => self.r[k] = value,
+                    "m" => self.m[k] = value,
+                    _ => unreachable!(),
+                }
+            }
+        }
+    }
+
+
+    pub fn get_parameter(&self, name: &str) -> f64
+    {
+        match name
+        {
+            "xi" => self.xi,
+            "v_0" => match self.velocities.distribution
+            {
+                particle::VelocityDistribution::Fixed(v_0) => v_0,
+                _ => f64::NAN,
+            },
+            "temperature" => match self.velocities.distribution
+            {
+                particle::VelocityDistribution::MaxwellBoltzmann(t) => t,
+                _ => f64::NAN,
+            },
+            "total_energy" => self.velocities.total_energy.unwrap_or(f64::NAN),
+            "x_max" => self.x_max,
+            "y_max" => self.y_max,
+            "events" => self.number_of_events as f64,
+            "energy_cutoff" => self.energy_cutoff_fraction,
+            "tc" => if self.tc { 1. } else { 0. },
+            _ =>
+            {
+                let (key, k) = self.split_species_parameter(name);
+                match key
+                {
+                    "n" => self.n[k] as f64,
+                    "r" => self.r[k],
+                    "m" => self.m[k],
+                    _ => unreachable!(),
+                }
+            }
+        }
+    }
+
+
+    // Returns the names of all parameters of the scenario.
+    pub fn get_parameter_names(&self) -> Vec<String>
+    {
+        let mut names: Vec<String> = ["xi", "v_0", "temperature", "total_energy",
+            "x_max", "y_max", "events", "energy_cutoff", "tc"]
+            .iter().map(|name| name.to_string()).collect();
+        for key in ["n", "r", "m"].iter()
+        {
+            for k in 0..self.n.len()
+            {
+                names.push(format!("{}_{}", key, k));
+            }
+        }
+        names
+    }
+
+
+    // Splits a name like "m_1" into ("m", 1).
+    fn split_species_parameter<'a>(&self, name: &'a str) -> (&'a str, usize)
+    {
+        let mut parts = name.splitn(2, '_');
+        let key = parts.next().unwrap_or("");
+        let index = parts.next().and_then(|k| k.parse::<usize>().ok());
+        match (key, index)
+        {
+            ("n", Some(k)) | ("r", Some(k)) | ("m", Some(k)) if k < self.n.len()
+                => (key, k),
+            _ => panic!("Unknown scenario parameter: {}", name),
+        }
+    }
+
+
+    // Generates the particles of the scenario. The random
+    // number generator is seeded with the seed of the scenario.
+    pub fn initiate(&self) -> particle::Particles
+    {
+        let mut rng = simulation::make_rng(self.seed);
+        let options = particle::GenerationOptions
+        {
+            sizes: self.sizes.clone(),
+            velocities: self.velocities.clone(),
+            placement: self.placement.clone(),
+            ..particle::GenerationOptions::new(self.n.len(), self.x_max, self.y_max)
+        };
+        let mut p = simulation::initiate_system(&self.n, &self.r, &self.m, &options, &mut rng);
+        p.species_names = self.species_names.clone();
+        p
+    }
+
+
+    // Generates the particles and fills their collision queue,
+    // ready to be stepped through event by event.
+    pub fn build(&self) -> simulation::Simulation
+    {
+        simulation::Simulation::with_queue(self.initiate(), self.x_max, self.y_max,
+            simulation::CollisionModel::new(self.xi, self.tc), parameters::T_0, self.queue)
+    }
+
+
+    // Returns when a run of the scenario stops, apart from
+    // after number_of_events events.
+    pub fn get_stop_condition(&self) -> stop::StopCondition
+    {
+        let energy = stop::StopCondition::EnergyFraction(self.energy_cutoff_fraction);
+        match &self.stop
+        {
+            Some(condition) => energy.or(condition.clone()),
+            None => energy,
+        }
+    }
+
+
+    // Generates particles and evolves them. If a trajectory
+    // format is set, the trajectory is written to <name>.<extension>,
+    // and if an event log format is set, the log to <name>_events.
+    pub fn simulate(&self) -> save_data::SaveResult<(particle::Particles, Array2<f64>, Array2<f64>)>
+    {
+        println!("Seed of the random number generator: {}", self.seed);
+        let mut sim = self.build();
+        let mut observers: Vec<Box<dyn Observer>> = Vec::new();
+        if let Some((format, every)) = self.trajectory
+        {
+            observers.push(Box::new(trajectory::TrajectoryWriter::new(&self.name, format, every)?));
+        }
+        if let Some(format) = self.event_log
+        {
+            observers.push(Box::new(event_log::EventLog::new(&self.name, format)?));
+        }
+        if let Some(every) = self.energy_stream
+        {
+            observers.push(Box::new(observer::EnergyWriter::new(&self.name, every)));
+        }
+        if let Some((every, style)) = self.dashboard
+        {
+            observers.push(Box::new(dashboard::Dashboard::new(every, 60, style)));
+        }
+
+        println!("Running simulation.");
+        let mut observers: Vec<&mut dyn Observer> = observers.iter_mut()
+            .map(|o| o.as_mut() as &mut dyn Observer).collect();
+        let (energy, speeds) = simulation::evolve_system(
+            &mut sim, self.number_of_events, &self.get_stop_condition(), &mut observers)?;
+
+        Ok((sim.into_particles(), energy, speeds))
+    }
+}
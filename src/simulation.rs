@@ -1,166 +1,463 @@
 extern crate ndarray;
 use ndarray::prelude::*;
 
+use ndarray_rand::rand::SeedableRng;
+use ndarray_rand::rand::rngs::StdRng;
+
+use std::cell::Cell;
+
 use crate::particle;
 use crate::parameters;
-use crate::plotting;
 use crate::collisions;
+use crate::indexed_queue;
+use crate::observer;
+use crate::stop::{RunStart, StopCondition};
+use crate::save_data;
+
+
+// How collisions dissipate energy. xi is the coefficient of
+// restitution. With the TC model, a collision that comes less
+// than parameters::TC_DT after the previous event is elastic,
+// which keeps the gas from collapsing inelastically.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct CollisionModel
+{
+    pub xi: f64,
+    pub tc: bool,
+}
 
 
-pub fn simulate_system(
-    n_arr: &Array1<usize>, 
-    r_arr: &Array1<f64>, 
-    m_arr: &Array1<f64>, 
-    //n: usize,
-    xi: f64, 
-    x_max: f64, 
-    y_max: f64)
--> (particle::Particles, Array2<f64>, Array2<f64>)
+impl CollisionModel
 {
-    let t_0 = parameters::T_0;
-    let mut p = initiate_system(n_arr, r_arr, m_arr, x_max, y_max);
-    let mut q = fill_queue(&p, t_0, x_max, y_max);
-    let n = parameters::NUMBER_OF_COLLISIONS;
+    pub fn new(xi: f64, tc: bool) -> CollisionModel
+    {
+        CollisionModel { xi, tc }
+    }
 
-    println!("Running simulation.");
-    let (energy, speeds) 
-        = evolve_system(&mut p, &mut q, n, t_0, &m_arr, &n_arr, xi, x_max, y_max, 0.0, false, false);
+    pub fn elastic() -> CollisionModel
+    {
+        CollisionModel::new(1.0, false)
+    }
 
-    return (p, energy, speeds);
+    // Whether a collision dt after the previous event is made elastic by the TC model.
+    pub fn is_tc_event(&self, dt: f64) -> bool
+    {
+        self.tc && dt < parameters::TC_DT
+    }
+
+    // Coefficient of restitution of a collision dt after the previous event.
+    pub fn get_xi(&self, dt: f64) -> f64
+    {
+        if self.is_tc_event(dt) { 1.0 } else { self.xi }
+    }
 }
 
 
-pub fn evolve_system(
-    mut p: &mut particle::Particles, 
-    q: &mut collisions::CollisionQueue, 
-    number_of_events: usize,
-    t_0: f64,
-    m_arr: &Array1<f64>,
-    n_arr: &Array1<usize>,
-    xi: f64,
+// A system of particles in a box, together with its queue of
+// upcoming collisions. Events are resolved one at a time with
+// step, or in bulk with run_events and run_until, so callers can
+// look at or record the system between any two events.
+//
+// Only the particles of an event are moved to the time of the
+// event, so each particle keeps the time its position was last
+// brought up to date. sync moves all of them to the current time.
+pub struct Simulation
+{
+    p: particle::Particles,
+    q: Box<dyn collisions::EventQueue>,
+    t: f64,
+    // Time of the previous event, which the model measures from.
+    // The time t can be moved past it by run_until.
+    last_event_time: f64,
     x_max: f64,
     y_max: f64,
-    energy_cutoff_fraction: f64,
-    tc: bool,
-    test: bool)
-    -> (Array2<f64>, Array2<f64>)
-{
-    let mut t = t_0;
-    let mut i: usize = 0;
-
-    // system_data contains some data about the system.
-    // index 0: time of collisions
-    // index 1: kinetic energy at these times
-    let mut system_data: Array2<f64> = Array::zeros((2+m_arr.len(), number_of_events));
+    model: CollisionModel,
+    events: usize,
+    tc_events: usize,
+    // Sum of the collision counts of all particles, kept up to
+    // date from the colliding particles.
+    collision_total: u64,
+    // Kinetic energy of each species, updated with the change of
+    // energy of the particles in every collision, and recomputed
+    // every parameters::ENERGY_CHECK_INTERVAL events.
+    species_energy: Vec<f64>,
+    checked_energy: f64,
+    max_energy_drift: f64,
+}
 
-    // speeds contains the speed of each particle
-    // before and after simulation.
-    let mut speeds: Array2<f64> = Array2::from_elem((1+m_arr.len(), p.get_len()), f64::NAN);
 
-    // Initial speed of all particles.
-    for j in 0..p.get_len()
+impl Simulation
+{
+    // Takes the particles as they are at time t_0, and fills
+    // a collisions::CollisionQueue with their upcoming collisions.
+    pub fn new(p: particle::Particles, x_max: f64, y_max: f64, model: CollisionModel, t_0: f64)
+        -> Simulation
     {
-        speeds[[0, j]] = p.get_speed(j);
+        Simulation::with_queue(p, x_max, y_max, model, t_0, indexed_queue::QueueKind::Heap)
     }
 
-    let e_i = p.get_tot_kinetic_energy();
-    let mut tc_events: i32 = 0;
-    println!("Evolving system.");
-    while i < number_of_events && p.get_tot_kinetic_energy() > e_i*energy_cutoff_fraction
+    // Like new, but with the given kind of queue.
+    pub fn with_queue(
+        mut p: particle::Particles,
+        x_max: f64,
+        y_max: f64,
+        model: CollisionModel,
+        t_0: f64,
+        queue: indexed_queue::QueueKind)
+        -> Simulation
     {
-        if !test
+        p.sync_time.fill(t_0);
+        let mut q = queue.make_queue(p.get_len());
+        fill_queue(q.as_mut(), &p, t_0, x_max, y_max);
+        let species_energy: Vec<f64> = (0..p.get_species_count())
+            .map(|k| p.get_kinetic_energy_for_species(k)).collect();
+        let checked_energy = species_energy.iter().sum();
+        let collision_total = p.collision_count.iter().map(|cc| *cc as u64).sum();
+        Simulation
         {
-            status_bar(i, number_of_events);
+            p, q, t: t_0, last_event_time: t_0, x_max, y_max, model, events: 0, tc_events: 0, collision_total,
+            species_energy, checked_energy, max_energy_drift: 0.,
         }
+    }
 
-        let c = q.pop_next();
-        if c.is_valid(p)
+    // Returns the time of the next collision, after dropping the
+    // outdated entries in front of it from the queue. None if no
+    // particle will ever collide again.
+    pub fn get_next_time(&mut self) -> Option<f64>
+    {
+        loop
         {
-            if test
+            let (time, valid) = match self.q.peek_next()
             {
-                print_particle_stats(&p);
-                print_collision_stats(&q);
-                plotting::plot_positions(&p, x_max, y_max);
-            }
-
-            system_data[[0, i]] = t;
-            system_data[[1, i]] = p.get_tot_kinetic_energy();
-            for j in 0..m_arr.len()
+                Some(c) => (c.get_time(), c.is_valid(&self.p)),
+                None => return None,
+            };
+            if valid
             {
-                system_data[[j + 2, i]] = p.get_kinetic_energy_for_mass(m_arr[j]);
+                return Some(time);
             }
+            self.q.discard_next(&self.p, self.t, self.x_max, self.y_max);
+        }
+    }
 
-            // t is time of previous collision,
-            // dt is time between previous and next collision.
-            let dt = c.get_time() - t;
-            t += dt;
-            i += 1;
+    // Moves the colliding particles to the next collision and
+    // resolves it. Returns None, and changes nothing, if there is none.
+    pub fn step(&mut self) -> Option<collisions::CollisionOutcome>
+    {
+        self.get_next_time()?;
+        let c = self.q.pop_next();
 
-            p.propagate(dt);
-    
-            // If using TC model, set xi to 1 if dt is small.
-            if tc == true && dt < parameters::TC_DT
-            {
-                q.resolve_next_collision(&c, &mut p, t, 1.0, x_max, y_max);
-                tc_events += 1;
-            }
-            else
+        // dt is the time between the previous and the next event.
+        let dt = c.get_time() - self.last_event_time;
+        self.t = c.get_time();
+        self.last_event_time = self.t;
+        let i = c.get_particle_1() as usize;
+        let j = c.get_particle_2();
+        self.p.sync_particle(i, self.t);
+        if j >= 0
+        {
+            self.p.sync_particle(j as usize, self.t);
+        }
+
+        if self.model.is_tc_event(dt)
+        {
+            self.tc_events += 1;
+        }
+        // Only the colliding particles change their energy.
+        let e_i = self.p.get_kinetic_energy(i);
+        let e_j = if j >= 0 { self.p.get_kinetic_energy(j as usize) } else { 0. };
+        let cc = self.p.get_collision_count(i as i32) + self.p.get_collision_count(j);
+
+        let outcome = self.q.resolve_next_collision(
+            &c, &mut self.p, self.t, self.model.get_xi(dt), self.x_max, self.y_max);
+
+        self.species_energy[self.p.species[i]] += self.p.get_kinetic_energy(i) - e_i;
+        if j >= 0
+        {
+            let j = j as usize;
+            self.species_energy[self.p.species[j]] += self.p.get_kinetic_energy(j) - e_j;
+        }
+        self.collision_total += (self.p.get_collision_count(i as i32)
+            + self.p.get_collision_count(j) - cc) as u64;
+        self.events += 1;
+        if self.events.is_multiple_of(parameters::ENERGY_CHECK_INTERVAL)
+        {
+            self.check_energy();
+        }
+        Some(outcome)
+    }
+
+    // Recomputes the energy of every species from the particles,
+    // and returns how far the kept energy had drifted from it.
+    // The rounding errors of an update are relative to the energy
+    // at the time, so the drift is relative to the energy at the
+    // previous check, which is the largest it has been since.
+    // A drift above parameters::ENERGY_DRIFT_TOLERANCE is only
+    // reported, since the kept energy is corrected here anyway.
+    pub fn check_energy(&mut self) -> f64
+    {
+        let kept = self.get_kinetic_energy();
+        for (k, e) in self.species_energy.iter_mut().enumerate()
+        {
+            *e = self.p.get_kinetic_energy_for_species(k);
+        }
+        let exact = self.get_kinetic_energy();
+        let scale = self.checked_energy.max(exact);
+        let drift = if kept == exact { 0. }
+            else if scale > 0. { (kept - exact).abs() / scale }
+            else { (kept - exact).abs() };
+        if drift >= parameters::ENERGY_DRIFT_TOLERANCE
+        {
+            println!("\nWARNING: The kept energy {} had drifted from the exact energy {} \
+                after {} events.", kept, exact, self.events);
+        }
+        self.checked_energy = exact;
+        self.max_energy_drift = self.max_energy_drift.max(drift);
+        drift
+    }
+
+    // Resolves the next n collisions, or fewer if the particles
+    // stop colliding. Returns the number that was resolved.
+    pub fn run_events(&mut self, n: usize) -> usize
+    {
+        for i in 0..n
+        {
+            if self.step().is_none()
             {
-                q.resolve_next_collision(&c, &mut p, t, xi, x_max, y_max);
+                return i;
             }
         }
+        n
     }
-    print!(" Done.\n");
-    println!("Quit simulation at {} events, with {:2} % of total energy remaining.",
-        i, 100.*p.get_tot_kinetic_energy()/e_i);
-    println!("Number of events that were modeled with xi = 1.0 (TC-model): {}", tc_events);
 
-    let mut k = 0;
-    for i in 0..m_arr.len()
+    // Resolves every collision up to time t, and then moves the
+    // time on to t. Returns the number of resolved collisions.
+    pub fn run_until(&mut self, t: f64) -> usize
     {
-        for j in 0..n_arr[i]
+        assert!(t >= self.t, "Can not run the simulation back in time.");
+        let mut n = 0;
+        while self.get_next_time().is_some_and(|time| time <= t)
         {
-            speeds[[i+1, j]] = p.get_speed(k+j);
+            self.step();
+            n += 1;
         }
-        k += n_arr[i]
+        // The queue holds absolute times, so it stays valid.
+        self.t = t;
+        n
+    }
+
+    // Brings the positions of all particles up to date.
+    pub fn sync(&mut self)
+    {
+        self.p.sync(self.t);
+    }
+
+    // The positions are only up to date after sync. Velocities,
+    // and everything else, are always up to date.
+    pub fn get_particles(&self) -> &particle::Particles
+    {
+        &self.p
+    }
+
+    // Returns the particles, with their positions up to date.
+    pub fn into_particles(mut self) -> particle::Particles
+    {
+        self.sync();
+        self.p
+    }
+
+    pub fn get_queue(&self) -> &dyn collisions::EventQueue
+    {
+        self.q.as_ref()
+    }
+
+    pub fn get_time(&self) -> f64
+    {
+        self.t
     }
 
-    return (system_data, speeds);
+    // Returns (x_max, y_max).
+    pub fn get_box(&self) -> (f64, f64)
+    {
+        (self.x_max, self.y_max)
+    }
+
+    pub fn get_model(&self) -> CollisionModel
+    {
+        self.model
+    }
+
+    // The model only affects collisions that have not been
+    // resolved yet, so it can be changed at any time.
+    pub fn set_model(&mut self, model: CollisionModel)
+    {
+        self.model = model;
+    }
+
+    // Total kinetic energy. Like the energy of every species,
+    // it is kept up to date without going through all particles.
+    pub fn get_kinetic_energy(&self) -> f64
+    {
+        self.species_energy.iter().sum()
+    }
+
+    pub fn get_kinetic_energy_for_species(&self, k: usize) -> f64
+    {
+        self.species_energy[k]
+    }
+
+    // Largest drift found by check_energy so far.
+    pub fn get_max_energy_drift(&self) -> f64
+    {
+        self.max_energy_drift
+    }
+
+    // Number of collisions resolved since the simulation was made.
+    pub fn get_event_count(&self) -> usize
+    {
+        self.events
+    }
+
+    // Number of those that the TC model made elastic.
+    pub fn get_tc_event_count(&self) -> usize
+    {
+        self.tc_events
+    }
+
+    // Same as Particles::get_avg_collision_count, without
+    // going through all particles.
+    pub fn get_avg_collision_count(&self) -> f64
+    {
+        self.collision_total as f64 / self.p.get_len() as f64
+    }
+}
+
+
+// Resolves collisions until the stop condition is met, or no
+// particle will collide again, and tells every observer about
+// it. Returns the number of events.
+pub fn run(
+    sim: &mut Simulation,
+    stop: &StopCondition,
+    observers: &mut [&mut dyn observer::Observer])
+    -> save_data::SaveResult<usize>
+{
+    let mut i: usize = 0;
+    let tc_events_0 = sim.get_tc_event_count();
+    let show_status = !observers.iter().any(|o| o.uses_terminal());
+
+    sim.sync();
+    for o in observers.iter_mut()
+    {
+        o.on_start(sim)?;
+        o.on_sample(sim, 0)?;
+    }
+
+    let start = RunStart::new(sim);
+    println!("Evolving system.");
+    while !stop.is_met(sim, &start)
+    {
+        if show_status
+        {
+            status_bar((1000.*stop.get_progress(sim, &start)) as usize, 1000);
+        }
+        let outcome = match sim.step()
+        {
+            Some(outcome) => outcome,
+            None => break,
+        };
+        i += 1;
+
+        for o in observers.iter_mut()
+        {
+            o.on_event(sim, i, &outcome)?;
+        }
+        // Positions are only brought up to date when they are needed.
+        let sampled = |o: &&mut dyn observer::Observer| i.is_multiple_of(o.get_sample_interval());
+        if observers.iter().any(|o| sampled(o) && o.needs_positions())
+        {
+            sim.sync();
+        }
+        for o in observers.iter_mut().filter(|o| sampled(o))
+        {
+            o.on_sample(sim, i)?;
+        }
+    }
+    print!(" Done.\n");
+    println!("Quit simulation at {} events, with {:2} % of total energy remaining.",
+        i, 100.*sim.get_kinetic_energy()/start.energy);
+    println!("Number of events that were modeled with xi = 1.0 (TC-model): {}",
+        sim.get_tc_event_count() - tc_events_0);
+
+    sim.sync();
+    for o in observers.iter_mut()
+    {
+        o.on_finish(sim, i)?;
+    }
+    Ok(i)
+}
+
+
+// Runs the simulation like run, for at most number_of_events
+// events, and returns the energies and speeds recorded by
+// observer::EnergyRecorder and SpeedRecorder. The energy is
+// recorded after every event, and from the last event, but
+// never more than parameters::MAX_ENERGY_SAMPLES times.
+pub fn evolve_system(
+    sim: &mut Simulation,
+    number_of_events: usize,
+    stop: &StopCondition,
+    observers: &mut [&mut dyn observer::Observer])
+    -> save_data::SaveResult<(Array2<f64>, Array2<f64>)>
+{
+    let mut energy = observer::EnergyRecorder::with_limit(1, parameters::MAX_ENERGY_SAMPLES);
+    let mut speeds = observer::SpeedRecorder::new();
+    let mut all: Vec<&mut dyn observer::Observer> = vec![&mut energy, &mut speeds];
+    all.extend(observers.iter_mut().map(|o| &mut **o as &mut dyn observer::Observer));
+    let stop = StopCondition::Events(number_of_events).or(stop.clone());
+    run(sim, &stop, &mut all)?;
+    drop(all);
+
+    Ok((energy.into_data(), speeds.into_data()))
+}
+
+
+// Returns the random number generator used to set up a system.
+// Equal seeds give equal systems, and therefore equal trajectories.
+pub fn make_rng(seed: u64) -> StdRng
+{
+    StdRng::seed_from_u64(seed)
 }
 
 
-pub fn initiate_system(n: &Array1<usize>, r: &Array1<f64>, m: &Array1<f64>, x_max: f64, y_max: f64) 
+pub fn initiate_system(
+    n: &Array1<usize>, 
+    r: &Array1<f64>, 
+    m: &Array1<f64>, 
+    options: &particle::GenerationOptions,
+    rng: &mut StdRng) 
 -> particle::Particles
 {
-    let p = particle::generate_particles(
-        n,
-        parameters::X_MIN,
-        x_max,
-        parameters::Y_MIN,
-        y_max,
-        r,
-        m);
+    let p = particle::generate_particles(n, r, m, options, rng);
     p
 }
 
 
-pub fn fill_queue(p: &particle::Particles, t_0: f64, x_max: f64, y_max: f64) 
-    -> collisions::CollisionQueue
+pub fn fill_queue(
+    q: &mut dyn collisions::EventQueue, p: &particle::Particles, t_0: f64, x_max: f64, y_max: f64)
 {
-    let mut q = collisions::CollisionQueue::new();
     println!("Filling collision queue.");
-    q.fill_collision_queue(&p, t_0, x_max, y_max);
+    q.fill_collision_queue(p, t_0, x_max, y_max);
 
     println!("Queue filled successfully.");
-    return q;
 }
 
 
-pub fn print_collision_stats(q: &collisions::CollisionQueue)
+pub fn print_collision_stats(q: &dyn collisions::EventQueue)
 {
     let mut i = 0;
     println!("Collision queue\n---------------------------------");
-    for c in q.get_heap()
+    for c in q.iter()
     {
         println!("Collision {}:\tt={:.2}, Particle {} colliding with particle {}", 
             i, c.get_time(), c.get_particle_1(), c.get_particle_2());
@@ -180,11 +477,28 @@ pub fn print_particle_stats(p: &particle::Particles)
     }
 }
 
+thread_local!
+{
+    // Whether status_bar prints anything on this thread.
+    // Turned off when simulations run in parallel.
+    static STATUS_BAR: Cell<bool> = const { Cell::new(true) };
+}
+
+pub fn set_status_bar(enabled: bool)
+{
+    STATUS_BAR.with(|s| s.set(enabled));
+}
+
 pub fn status_bar(i: usize, max: usize)
 {
+    if !STATUS_BAR.with(|s| s.get())
+    {
+        return;
+    }
     let length: usize = 50;
-    let prog = "=".repeat(length*i/max);
-    let spaces = " ".repeat(length-prog.len()-1);
+    // i can reach max, when a run is done before it stops.
+    let prog = "=".repeat((length*i/max).min(length - 1));
+    let spaces = " ".repeat(length - prog.len() - 1);
     print!("\rProgress: [{}>{}]", prog, spaces);
 }
 
@@ -0,0 +1,136 @@
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use crate::simulation::Simulation;
+
+
+// When simulation::run stops. Conditions are checked before
+// every event, so a run stops at the first event after which
+// its condition is met. Events, energy and wall clock time are
+// counted from the start of the run, simulated time from the
+// start of the simulation.
+#[derive(Clone)]
+pub enum StopCondition
+{
+    Time(f64),
+    Events(usize),
+    CollisionsPerParticle(f64),  // Average collision count of the particles
+    EnergyFraction(f64),         // Stops when the energy is at or below this fraction
+    WallClock(Duration),
+    Predicate(Arc<dyn Fn(&Simulation) -> bool + Send + Sync>),
+    Any(Vec<StopCondition>),
+    All(Vec<StopCondition>),
+}
+
+
+// What the conditions of a run are measured against.
+pub struct RunStart
+{
+    pub events: usize,
+    pub energy: f64,
+    pub instant: Instant,
+}
+
+
+impl RunStart
+{
+    pub fn new(sim: &Simulation) -> RunStart
+    {
+        RunStart
+        {
+            events: sim.get_event_count(),
+            energy: sim.get_kinetic_energy(),
+            instant: Instant::now(),
+        }
+    }
+}
+
+
+impl StopCondition
+{
+    // A condition that is never met. The run then stops
+    // only when no particle will collide again.
+    pub fn never() -> StopCondition
+    {
+        StopCondition::Any(Vec::new())
+    }
+
+    pub fn predicate<F>(f: F) -> StopCondition
+        where F: Fn(&Simulation) -> bool + Send + Sync + 'static
+    {
+        StopCondition::Predicate(Arc::new(f))
+    }
+
+    // Stops when either this or the other condition is met.
+    pub fn or(self, other: StopCondition) -> StopCondition
+    {
+        match self
+        {
+            StopCondition::Any(mut conditions) =>
+            {
+                conditions.push(other);
+                StopCondition::Any(conditions)
+            }
+            _ => StopCondition::Any(vec![self, other]),
+        }
+    }
+
+    // Stops when both this and the other condition are met.
+    pub fn and(self, other: StopCondition) -> StopCondition
+    {
+        match self
+        {
+            StopCondition::All(mut conditions) =>
+            {
+                conditions.push(other);
+                StopCondition::All(conditions)
+            }
+            _ => StopCondition::All(vec![self, other]),
+        }
+    }
+
+    pub fn is_met(&self, sim: &Simulation, start: &RunStart) -> bool
+    {
+        match self
+        {
+            StopCondition::Time(t) => sim.get_time() >= *t,
+            StopCondition::Events(n) => sim.get_event_count() - start.events >= *n,
+            StopCondition::CollisionsPerParticle(c) => sim.get_avg_collision_count() >= *c,
+            StopCondition::EnergyFraction(f) =>
+                sim.get_kinetic_energy() <= f*start.energy,
+            StopCondition::WallClock(budget) => start.instant.elapsed() >= *budget,
+            StopCondition::Predicate(f) => f(sim),
+            StopCondition::Any(conditions) => conditions.iter().any(|c| c.is_met(sim, start)),
+            StopCondition::All(conditions) => conditions.iter().all(|c| c.is_met(sim, start)),
+        }
+    }
+
+    // Estimates how far the run has come, from 0 to 1, for
+    // the status bar. Predicates give no estimate, so they
+    // count as 0.
+    pub fn get_progress(&self, sim: &Simulation, start: &RunStart) -> f64
+    {
+        let progress = match self
+        {
+            StopCondition::Time(t) => sim.get_time() / t,
+            StopCondition::Events(n) => (sim.get_event_count() - start.events) as f64 / *n as f64,
+            StopCondition::CollisionsPerParticle(c) => sim.get_avg_collision_count() / c,
+            StopCondition::EnergyFraction(f) =>
+            {
+                // The energy decays roughly exponentially at first,
+                // so progress is measured on a log scale.
+                let e = sim.get_kinetic_energy() / start.energy;
+                if *f > 0. && *f < 1. { e.ln() / f.ln() } else { 0. }
+            }
+            StopCondition::WallClock(budget) =>
+                start.instant.elapsed().as_secs_f64() / budget.as_secs_f64(),
+            StopCondition::Predicate(_) => 0.,
+            StopCondition::Any(conditions) => conditions.iter()
+                .map(|c| c.get_progress(sim, start)).fold(0., f64::max),
+            StopCondition::All(conditions) => conditions.iter()
+                .map(|c| c.get_progress(sim, start)).fold(1., f64::min),
+        };
+        if progress.is_finite() { progress.clamp(0., 1.) } else { 0. }
+    }
+}
+
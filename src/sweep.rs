@@ -0,0 +1,139 @@
+use ndarray::prelude::*;
+
+use crate::parallel;
+use crate::save_data;
+use crate::scenario::Scenario;
+
+
+// A parameter sweep over one or more scenario parameters.
+// If several parameters are added, every combination of
+// their values is run (the Cartesian product).
+pub struct Sweep
+{
+    name: String,
+    metrics: Vec<String>,
+    parameters: Vec<String>,
+    values: Vec<Vec<f64>>,
+    workers: usize,
+}
+
+
+impl Sweep
+{
+    // name is used for the output directory and the summary file,
+    // metrics are the column names of the scalars returned by each run,
+    // such as a measured value and its standard error.
+    pub fn new(name: &str, metrics: &[&str]) -> Sweep
+    {
+        assert!(!metrics.is_empty(), "A sweep needs at least one metric.");
+        Sweep
+        {
+            name: name.to_owned(),
+            metrics: metrics.iter().map(|m| m.to_string()).collect(),
+            parameters: Vec::new(),
+            values: Vec::new(),
+            workers: 1,
+        }
+    }
+
+    pub fn add_values(&mut self, parameter: &str, values: &[f64]) -> &mut Sweep
+    {
+        assert!(!values.is_empty(), "A sweep parameter needs at least one value.");
+        self.parameters.push(parameter.to_owned());
+        self.values.push(values.to_vec());
+        self
+    }
+
+    pub fn add_linspace(&mut self, parameter: &str, start: f64, end: f64, n: usize)
+        -> &mut Sweep
+    {
+        let values = Array::linspace(start, end, n).to_vec();
+        self.add_values(parameter, &values)
+    }
+
+    // Sets how many points of the sweep are run at the same time.
+    pub fn set_workers(&mut self, workers: usize) -> &mut Sweep
+    {
+        assert!(workers > 0, "A sweep needs at least one worker.");
+        self.workers = workers;
+        self
+    }
+
+    pub fn get_len(&self) -> usize
+    {
+        self.values.iter().map(|v| v.len()).product()
+    }
+
+    // Returns the values of every parameter at each point of the sweep.
+    // The last parameter added varies fastest.
+    pub fn get_points(&self) -> Vec<Vec<f64>>
+    {
+        let mut points: Vec<Vec<f64>> = vec![Vec::new()];
+        for values in self.values.iter()
+        {
+            let mut next = Vec::with_capacity(points.len() * values.len());
+            for point in points.iter()
+            {
+                for v in values.iter()
+                {
+                    let mut p = point.clone();
+                    p.push(*v);
+                    next.push(p);
+                }
+            }
+            points = next;
+        }
+        points
+    }
+
+    // Runs every point of the sweep, starting from the base scenario.
+    // run_point gets the scenario of the point and a filename prefix
+    // inside the sweep directory, and returns the metrics of the run.
+    // A summary with one row per point is written to file and returned,
+    // with the parameters in the first rows and the metrics in the last.
+    pub fn run<F>(&self, base: &Scenario, run_point: F)
+        -> save_data::SaveResult<Array2<f64>>
+        where F: Fn(&Scenario, &str) -> save_data::SaveResult<Vec<f64>> + Sync
+    {
+        let dir = save_data::create_run_dir(&self.name)?;
+        let points = self.get_points();
+        let n_params = self.parameters.len();
+        let mut summary = Array2::zeros((n_params + self.metrics.len(), points.len()));
+
+        let mut scenarios = Vec::with_capacity(points.len());
+        for (k, point) in points.iter().enumerate()
+        {
+            let mut scenario = base.clone();
+            scenario.name = format!("{}/point_{:03}", dir, k);
+            println!("Sweep point {} of {}:", k + 1, self.get_len());
+            for (j, value) in point.iter().enumerate()
+            {
+                let parameter = &self.parameters[j];
+                scenario.set_parameter(parameter, *value);
+                summary[[j, k]] = scenario.get_parameter(parameter);
+                println!("{} = {:.3}", parameter, summary[[j, k]]);
+            }
+            save_data::scenario_to_file(&scenario, &scenario.name)?;
+            scenarios.push(scenario);
+        }
+        println!();
+
+        let metrics = parallel::run_parallel(scenarios.len(), self.workers,
+            |k| run_point(&scenarios[k], &scenarios[k].name));
+        for (k, values) in metrics.into_iter().enumerate()
+        {
+            let values = values?;
+            assert_eq!(values.len(), self.metrics.len(),
+                "A sweep point returned the wrong number of metrics.");
+            for (j, value) in values.iter().enumerate()
+            {
+                summary[[n_params + j, k]] = *value;
+            }
+        }
+
+        let mut names = self.parameters.clone();
+        names.extend(self.metrics.iter().cloned());
+        save_data::summary_to_file(&names, &summary, &format!("{}/{}", dir, self.name))?;
+        Ok(summary)
+    }
+}
@@ -1,65 +1,74 @@
 use ndarray::prelude::*;
-use crate::simulation;
-use crate::plotting;
-use crate::particle;
-use crate::save_data;
-
-
-pub fn tasks_main()
+use granular_gas::simulation;
+use granular_gas::plotting;
+use granular_gas::particle;
+use granular_gas::save_data;
+use granular_gas::parallel;
+use granular_gas::scenario::Scenario;
+use granular_gas::sweep::Sweep;
+use granular_gas::ensemble::{Ensemble, Realization};
+use granular_gas::render;
+use granular_gas::observer::Observer;
+use granular_gas::stop::StopCondition;
+
+
+pub fn tasks_main() -> save_data::SaveResult<()>
 {
-    //task_1();
-    task_2();
-    task_3();
+    //task_1()?;
+    task_2()?;
+    task_3()?;
     //Task 4 is done, I think.
-    ////task_4();
+    ////task_4()?;
+    Ok(())
 }
 
-fn task_1() 
+fn task_1() -> save_data::SaveResult<()>
 {
     let n: Array1<usize> = array![4000];
     let r: Array1<f64> = array![0.001];
     let m: Array1<f64> = array![0.001];
 
-    let xi = 1.0;
-    let x_max = 1.0;
-    let y_max = 1.0;
     print_task_info(1, &n, &r, &m);
+    let mut s = Scenario::new("task_1_final", n, r, m);
+    s.name = save_data::create_run_dir(&s.name)? + "/" + &s.name;
     println!("Packing fraction of particles: {}", 
-        particle::get_packing_fraction(&n, &r, 0., 0., x_max, y_max));
+        particle::get_packing_fraction(&s.n, &s.r, 0., 0., s.x_max, s.y_max));
     
-    let (p, energy, speeds) = simulation::simulate_system(&n, &r, &m, 
-        xi, x_max, y_max);
+    let (p, energy, speeds) = s.simulate()?;
 
-    save_data::particles_to_file(&p, "task_1_final");
-    save_data::speed_to_file(&speeds, "task_1_final");
-    save_data::energy_to_file(&energy, "task_1_final");
+    save_data::scenario_to_file(&s, &s.name)?;
+    save_data::particles_to_file(&p, &s.name)?;
+    save_data::speed_to_file(&speeds, &s.species_names, &s.name)?;
+    save_data::energy_to_file(&energy, &s.species_names, &s.name)?;
 
-    //plotting::plot_energy_single_mass(&energy);
+    //plotting::plot_energy(&energy, &s.species_names, &plotting::EnergyPlot::default(), &plotting::PlotOutput::Show);
     //plotting::plot_stats(speeds.slice(s![0,..]), speeds.slice(s![1,..]));
     println!("");
-
+    Ok(())
 }
 
 
-fn task_2() 
+fn task_2() -> save_data::SaveResult<()>
 {
     let n: Array1<usize> = array![2000, 2000];
     let r: Array1<f64> = array![0.001, 0.002];
     let m: Array1<f64> = array![0.001, 0.004];
 
-    let xi = 1.0;
-    let x_max = 1.0;
-    let y_max = 1.0;
     print_task_info(2, &n, &r, &m);
-    let (p, energy, speeds) = simulation::simulate_system(&n, &r, &m, xi, x_max, y_max);
+    let mut s = Scenario::new("task_2_diff_r", n, r, m);
+    s.set_species_names(&["small", "large"]);
+    s.name = save_data::create_run_dir(&s.name)? + "/" + &s.name;
+    let (p, energy, speeds) = s.simulate()?;
 
-    save_data::particles_to_file(&p, "task_2_diff_r");
-    save_data::speed_to_file(&speeds, "task_2_diff_r");
-    save_data::energy_to_file(&energy, "task_2_diff_r");
+    save_data::scenario_to_file(&s, &s.name)?;
+    save_data::particles_to_file(&p, &s.name)?;
+    save_data::speed_to_file(&speeds, &s.species_names, &s.name)?;
+    save_data::energy_to_file(&energy, &s.species_names, &s.name)?;
 
-    //plotting::plot_energy_single_mass(&energy);
+    //plotting::plot_energy(&energy, &s.species_names, &plotting::EnergyPlot::default(), &plotting::PlotOutput::Show);
     //plotting::plot_stats(speeds.slice(s![0,..]), speeds.slice(s![1,..]));
     println!("");
+    Ok(())
 }
 
 
@@ -67,103 +76,139 @@ fn task_2()
 // might not be necessary in order
 // to implement a stopping criterium.
 // The average number is known before start.
-fn task_3() 
+fn task_3() -> save_data::SaveResult<()>
 {
     let n: Array1<usize> = array![1000, 1000];
     let r: Array1<f64> = array![0.001, 0.002];
     let m: Array1<f64> = array![0.001, 0.004];
-    let x_max = 1.0;
-    let y_max = 1.0;
     print_task_info(3, &n, &r, &m);
-
-    for xi in [1.0, 0.9, 0.8].iter()
+    let mut base = Scenario::new("task_3", n, r, m);
+    base.set_species_names(&["small", "large"]);
+    // Every run stops at the same average number of
+    // collisions per particle, rather than of events.
+    base.number_of_events = 200000;
+    base.stop = Some(StopCondition::CollisionsPerParticle(50.));
+
+    let mut sweep = Sweep::new("task_3", &["energy_fraction"]);
+    sweep.add_values("xi", &[1.0, 0.9, 0.8]);
+    sweep.set_workers(parallel::available_workers());
+    sweep.run(&base, |s, filename|
     {
-        println!("Restitution coefficient Xi = {}", xi);
-        let (p, energy, speeds) 
-            = simulation::simulate_system(&n, &r, &m, *xi, x_max, y_max);
-
-        save_data::particles_to_file(&p, &format!("{}{}", "task_3_", xi));
-        save_data::speed_to_file(&speeds, &format!("{}{}", &"task_3_", xi));
-        save_data::energy_to_file(&energy, &format!("{}{}", &"task_3_", xi));
-        //plotting::plot_energy_two_masses(&energy);
+        let (p, energy, speeds) = s.simulate()?;
+
+        save_data::particles_to_file(&p, filename)?;
+        save_data::speed_to_file(&speeds, &s.species_names, filename)?;
+        save_data::energy_to_file(&energy, &s.species_names, filename)?;
+        //plotting::plot_energy(&energy, &s.species_names, &plotting::EnergyPlot::default(), &plotting::PlotOutput::png(filename, 800, 600));
         //plotting::plot_stats(speeds.slice(s![0,..]), speeds.slice(s![1,..]));
-        println!("");
-    }
+        Ok(vec![energy[[1, energy.ncols() - 1]] / energy[[1, 0]]])
+    })?;
+    Ok(())
 }
 
 // Task 4 is different from the previous three.
 // There will be a need to change the other functions.
-fn task_4()
+fn task_4() -> save_data::SaveResult<()>
 {   
-    let x_max = 1.0;
-    let mut y_max = 0.5;
-
     // A particle with radius 0 will never collide with other particles.
     let wall_amount: usize = 1800;
     let wall_radius = 0.005;
 
-    let projectile_mass: f64 = 2.5;
+    // The projectile is given its radius, mass and speed
+    // from the scenario, so any of these can be swept.
     let n: Array1<usize> = array![wall_amount, 1];
-    let r: Array1<f64> = array![wall_radius, 0.0];
-    let m: Array1<f64> = array![0.1, projectile_mass];
-
-
+    let r: Array1<f64> = array![wall_radius, 0.1];
+    let m: Array1<f64> = array![0.1, 10.];
     print_task_info(4, &n, &r, &m);
 
-    // Both initiate_system and evolve_system 
-    // work well, even for high packing fractions.
-    // If more speed is needed, initiating the 
-    // system with a grid and then propagating might be faster.
-    let mut particles_init = simulation::initiate_system(&n, &r, &m, x_max, y_max);
+    let mut base = Scenario::new("task_4", n, r, m);
+    base.set_species_names(&["bed", "projectile"]);
+    base.y_max = 1.0;
+    base.set_parameter("v_0", 3.0);
+    base.energy_cutoff_fraction = 0.10;
+    base.number_of_events = 10000;
+    base.tc = true;
+
+    // The crater size of a single run is noisy, so
+    // every point of the sweep is an ensemble of runs.
+    let realizations = 8;
+    let mut sweep = Sweep::new("task_4_low", &["size", "size_err"]);
+    sweep.add_linspace("xi", 0.01, 0.07, 4);
+    //sweep.add_values("v_0", &[1.0, 3.0, 5.0]);
+    //sweep.add_values("m_1", &[1.0, 10.0]);
+    sweep.set_workers(parallel::available_workers());
+    sweep.run(&base, |s, filename|
+    {
+        let ensemble = Ensemble::new(filename, &["size"], realizations);
+        let average = ensemble.run(s, |s, k|
+        {
+            // Other velocity distributions give the projectile no single speed.
+            let v_0 = match s.velocities.distribution
+            {
+                particle::VelocityDistribution::Fixed(v_0) => v_0,
+                ref d => return Err(format!("The projectile needs a fixed speed, not {:?}.", d).into()),
+            };
+            let particles_init = generate_crater_bed(s, 0.5, v_0);
+            if k == 0
+            {
+                save_data::particles_to_file(&particles_init, &format!("{}_initial", s.name))?;
+            }
+            let mut sim = simulation::Simulation::new(particles_init.copy(), s.x_max, s.y_max,
+                simulation::CollisionModel::new(s.xi, s.tc), 0.);
+
+            // The first realization is animated, so that the
+            // crater formation can be watched afterwards.
+            let mut animation = render::Animation::new(&s.name, render::AnimationFormat::Gif(4),
+                s.number_of_events / 200, 400, render::ColorBy::Speed(v_0));
+            let frames: &mut [&mut dyn Observer] = if k == 0 { &mut [&mut animation] } else { &mut [] };
+
+            //plotting::plot_positions(&particles, x_max, 1.0);
+            let (energy, speeds) = simulation::evolve_system(&mut sim,
+                s.number_of_events, &s.get_stop_condition(), frames)?;
+            let particles = sim.into_particles();
+
+            if k == 0
+            {
+                save_data::particles_to_file(&particles, &s.name)?;
+                save_data::speed_to_file(&speeds, &s.species_names, &s.name)?;
+                save_data::energy_to_file(&energy, &s.species_names, &s.name)?;
+                //plotting::plot_positions(&particles, x_max, 1.0);
+                //plotting::plot_energy(&energy, &s.species_names, &plotting::EnergyPlot::default(), &plotting::PlotOutput::Show);
+            }
+            let size = get_crater_size(&particles_init, &particles, 0.5);
+            Ok(Realization { energy, speeds, metrics: vec![size] })
+        })?;
+        Ok(vec![average.metrics[[0, 0]], average.metrics[[1, 0]]])
+    })?;
+    Ok(())
+}
 
-    println!("Packing fraction of particles: {}", 
-        particle::get_packing_fraction(&n, &r, 0., 0., x_max, y_max));
-    particles_init.stop_all_particles();
-    particles_init.set_particle_state(wall_amount, 0.5, 0.75, 0., -5.0, 0.025, projectile_mass);
-    save_data::particles_to_file(&particles_init, "task_4_initial");
-
-    y_max = 1.0;
-    //let xi = 0.5;
-    let v_0 = 3.0;
-    let energy_cutoff_fraction = 0.10;
-    let max_number_of_events = 10000;
-    let number_of_scans = 4;
-    let mut crater_sizes = Array2::zeros((2, number_of_scans));
-    let mut vals = Array::linspace(0.01, 0.07, number_of_scans);
-
-
-    for (i, val) in vals.iter().enumerate()
+// Generates a bed of resting particles of the first species, filling
+// the box up to bed_height, and places the projectile (the second
+// species) above it, moving downwards with speed v_0.
+fn generate_crater_bed(s: &Scenario, bed_height: f64, v_0: f64) -> particle::Particles
+{
+    // Random placement can not make a dense bed, so the particles are
+    // placed at a low packing fraction and then compressed. If the bed
+    // is not denser than initial_fraction, this is just random placement.
+    let r_bed = array![s.r[0], 0.0];
+    let mut rng = simulation::make_rng(s.seed);
+    let options = particle::GenerationOptions
     {
-        // Here, set the correct value (xi, v_0, m_i) to be 
-        // equal to val, in order to do the parameter sweep.
-        let xi = *val;
-        println!("xi = {:.3}", val);
-        //particles.m[wall_amount] = *m_i;
-        //let m = array![0.01, *m_i];
-        let mut particles = particles_init.copy();
-        particles.set_particle_state(wall_amount, 0.5, 0.75, 0., -v_0, 0.1, 10.);
-        let mut q = simulation::fill_queue(&particles, 0., x_max, y_max);
-
-        //plotting::plot_positions(&particles, x_max, 1.0);
-        let (energy, speeds) = simulation::evolve_system(&mut particles, &mut q, 
-            max_number_of_events, 0., &m, &n, xi, x_max, y_max, energy_cutoff_fraction, true, false);
-
-        crater_sizes[[0, i]] = xi;
-        crater_sizes[[1, i]] = get_crater_size(&particles_init, &particles, 0.5);
-
-        //if i == 0 || i == 3 || i == 6 || i == 9 || i == 12
-        //{
-        //    let filename = format!("{}{:.2}", "task_4_final_", val);
-        //    save_data::particles_to_file(&particles, &filename);
-        //    save_data::speed_to_file(&speeds, &filename);
-        //    save_data::energy_to_file(&energy, &filename);
-        //    //plotting::plot_positions(&particles, x_max, 1.0);
-        //    //plotting::plot_energy_two_masses(&energy);
-        //}
-        println!("");
-    }
-    save_data::crater_size_to_file(&crater_sizes, "task_4_low");
+        sizes: s.sizes.clone(),
+        placement: particle::Placement::Compressed { 
+            initial_fraction: 0.3, growth_rate: 0.01, max_events: 100000000 },
+        ..particle::GenerationOptions::new(s.n.len(), s.x_max, bed_height)
+    };
+    let mut particles = simulation::initiate_system(&s.n, &r_bed, &s.m, &options, &mut rng);
+    particles.species_names = s.species_names.clone();
 
+    println!("Packing fraction of particles: {}", 
+        particle::get_packing_fraction(&s.n, &r_bed, 0., 0., s.x_max, bed_height));
+    particles.stop_all_particles();
+    let projectile = s.n[0];
+    particles.set_particle_state(projectile, 0.5*s.x_max, 0.75*s.y_max, 0., -v_0, s.r[1], s.m[1]);
+    particles
 }
 
 fn get_crater_size(
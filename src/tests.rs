@@ -1,11 +1,25 @@
 extern crate ndarray;
 use ndarray::prelude::*;
 
-use crate::particle;
-use crate::parameters;
-use crate::simulation;
-use crate::plotting;
-use crate::save_data;
+use granular_gas::particle;
+use granular_gas::parameters;
+use granular_gas::simulation;
+use granular_gas::plotting;
+use granular_gas::save_data;
+use granular_gas::scenario::Scenario;
+use granular_gas::trajectory;
+use granular_gas::event_log;
+use granular_gas::render;
+use granular_gas::dashboard;
+use granular_gas::observer::{self, Observer, TestPrinter};
+use granular_gas::stop::StopCondition;
+use granular_gas::ensemble::{Ensemble, Realization};
+use granular_gas::indexed_queue::QueueKind;
+use granular_gas::parallel;
+use granular_gas::compression;
+use granular_gas::sweep::Sweep;
+
+use std::time::Instant;
 
 pub fn test_main()
 {
@@ -16,96 +30,130 @@ pub fn test_main()
     //test_some_particles();
     //test_many_particles();
     test_save_data();
+    test_ensemble();
+    test_reproducibility();
+    test_placement();
+    test_compression();
+    test_velocity_distributions();
+    test_polydisperse();
+    test_species();
+    test_trajectory();
+    test_event_log();
+    test_run_dirs();
+    test_sweep();
+    test_render();
+    test_haff_fit();
+    test_dashboard();
+    test_stepping();
+    test_observer();
+    test_stop_conditions();
+    test_recording();
+    test_energy_bookkeeping();
+    test_lazy_positions();
+    test_indexed_queue();
+    test_parallel_queue();
 }
 
 
 fn test_one_particle()
 {
-    let mut p = particle::Particles
+    let p = particle::Particles
     {
         pos: arr2(&[[0.2], [0.3]]),
         vel: arr2(&[[1.], [1.]]),
         r: Array1::from_elem(1, 0.01),
         m: Array1::from_elem(1, 0.01),
         collision_count: Array1::zeros(1),
+        species: Array1::zeros(1),
+        species_names: vec!["0".to_owned()],
+        sync_time: Array1::zeros(1),
     };
     let xi = 1.0;
     let x_max = 1.0;
     let y_max = 1.0;
-    let mut q = simulation::fill_queue(&p, 0., x_max, y_max);
+    let mut sim = simulation::Simulation::new(
+        p, x_max, y_max, simulation::CollisionModel::new(xi, false), 0.);
     
     println!("Running simulation with one particle.");
     println!("Behaving correctly, it should collide with \
     all four walls before returning to it's starting point \
     (x, y) = ({}, {})", 0.5, 0.5);
-    simulation::evolve_system(&mut p, &mut q, 5, 0., &array![], &array![1], xi, x_max, y_max, 0.0, false, false);
+    simulation::evolve_system(&mut sim, 5, &StopCondition::never(), &mut []).unwrap();
 
 }
 
 fn test_two_particles()
 {
-    let mut p = particle::Particles
+    let p = particle::Particles
     {
         pos: arr2(&[[0.3, 0.7], [0.5, 0.5]]),
         vel: arr2(&[[1., -1.], [0., 0.]]),
         r: Array1::from_elem(2, 0.01),
         m: Array1::from_elem(2, 0.01),
         collision_count: Array1::zeros(2),
+        species: Array1::zeros(2),
+        species_names: vec!["0".to_owned()],
+        sync_time: Array1::zeros(2),
     };
     let xi = 1.0;
     let x_max = 1.0;
     let y_max = 1.0;
-    let mut q = simulation::fill_queue(&p, 0., x_max, y_max);
+    let mut sim = simulation::Simulation::new(
+        p, x_max, y_max, simulation::CollisionModel::new(xi, false), 0.);
     
     println!("Running simulation with two particles.");
     println!("Behaving correctly, thay should collide with \
     each other, then the walls, repeatedly.");
-    simulation::evolve_system(&mut p, &mut q, 5, 0., &array![], &array![1], xi, x_max, y_max, 0.0, false, true);
+    simulation::evolve_system(&mut sim, 5, &StopCondition::never(), &mut [&mut TestPrinter]).unwrap();
 }
 
 
 fn test_collision_angle()
 {
-    let mut p = particle::Particles
+    let p = particle::Particles
     {
         pos: arr2(&[[0.3, 0.7], [0.6, 0.5]]),
         vel: arr2(&[[1., 0.], [0., 0.]]),
         r: arr1(&[0.001, 0.1]),
         m: arr1(&[1., 1e6]),
         collision_count: Array1::zeros(2),       
+        species: arr1(&[0, 1]),
+        species_names: vec!["small".to_owned(), "large".to_owned()],
+        sync_time: Array1::zeros(2),
     };
     let xi = 1.0;
     let x_max = 1.0;
     let y_max = 1.0;
-    let mut q = simulation::fill_queue(&p, 0., x_max, y_max);
+    let mut sim = simulation::Simulation::new(
+        p, x_max, y_max, simulation::CollisionModel::new(xi, false), 0.);
     
     println!("Running simulation with two \
     particles of vastly different size.");
     println!("Behaving correctly, they should collide with \
     each other, then the smaller particle will change direction.");
-    simulation::evolve_system(&mut p, &mut q, 5, 0., &array![1., 1e6], &array![1, 1], xi, x_max, y_max, 0.0, false, true);
+    simulation::evolve_system(&mut sim, 5, &StopCondition::never(), &mut [&mut TestPrinter]).unwrap();
 }
 
 fn test_some_particles()
 {
     let x_max = 1.5;
     let y_max = 0.4;
-    let mut p = particle::generate_particles(
+    let p = particle::generate_particles(
         &array![10],
-        parameters::X_MIN,
-        x_max,
-        parameters::Y_MIN,
-        y_max,
         &array![0.01],
-        &array![1.]);
+        &array![1.],
+        &particle::GenerationOptions::new(1, x_max, y_max),
+        &mut simulation::make_rng(parameters::SEED));
     let xi = 1.0;
-    let mut q = simulation::fill_queue(&p, 0., x_max, y_max);
+    let mut sim = simulation::Simulation::new(
+        p, x_max, y_max, simulation::CollisionModel::new(xi, false), 0.);
     println!("Running simulation with a small number of particles.");
     println!("Velocity and collision data will be printed.");
     let (energy, _speeds) = simulation::evolve_system(
-        &mut p,&mut q, 5, 0., &array![0.01], &array![10], xi, x_max, y_max, 0.0, false, true);
+        &mut sim, 5, &StopCondition::never(), &mut [&mut TestPrinter]).unwrap();
+    let p = sim.into_particles();
 
-    plotting::plot_energy_single_mass(&energy);
+    plotting::plot_energy(&energy, &p.species_names, &plotting::EnergyPlot::default(), &plotting::PlotOutput::Show);
     //plotting::plot_stats(speeds.slice(s![0,..]), speeds.slice(s![1,..]));
 }
 
@@ -116,23 +164,22 @@ fn test_many_particles()
     let x_max = 1.0;
     let y_max = 1.0;
 
-    let mut p = particle::generate_particles(
+    let p = particle::generate_particles(
         &array![100],
-        parameters::X_MIN,
-        x_max,
-        parameters::Y_MIN,
-        y_max,
         &array![0.001],
-        &array![1.]);    
+        &array![1.],
+        &particle::GenerationOptions::new(1, x_max, y_max),
+        &mut simulation::make_rng(parameters::SEED));
     let xi = 1.0;
-    let mut q = simulation::fill_queue(&p, 0., x_max, y_max);
+    let mut sim = simulation::Simulation::new(
+        p, x_max, y_max, simulation::CollisionModel::new(xi, false), 0.);
     println!("Running simulation with many particles.");
     println!("Energy should remain constant.");
-    let (energy, _speeds) = simulation::evolve_system(
-        &mut p ,&mut q, 500, 0., &array![0.01], &array![100], xi, x_max, y_max, 0.0, false, false);
+    let (energy, _speeds) = simulation::evolve_system(&mut sim, 500, &StopCondition::never(), &mut []).unwrap();
+    let p = sim.into_particles();
 
     //save_data::particles_to_file(&p, "save_test");
-    plotting::plot_energy_single_mass(&energy);
+    plotting::plot_energy(&energy, &p.species_names, &plotting::EnergyPlot::default(), &plotting::PlotOutput::Show);
     //plotting::plot_stats(speeds.slice(s![0,..]), speeds.slice(s![1,..]));
 }
 
@@ -143,29 +190,854 @@ fn test_save_data()
     let x_max = 1.0;
     let y_max = 1.0;
 
-    let mut p = particle::generate_particles(
+    let p = particle::generate_particles(
         &array![100],
-        parameters::X_MIN,
-        x_max,
-        parameters::Y_MIN,
-        y_max,
         &array![0.001],
-        &array![1.]);    
+        &array![1.],
+        &particle::GenerationOptions::new(1, x_max, y_max),
+        &mut simulation::make_rng(parameters::SEED));
     let xi = 1.0;
-    let mut q = simulation::fill_queue(&p, 0., x_max, y_max);
+    let mut sim = simulation::Simulation::new(
+        p, x_max, y_max, simulation::CollisionModel::new(xi, false), 0.);
     println!("Running simulation with many particles.");
     println!("Energy should remain constant.");
-    let (energy, speeds) = simulation::evolve_system(
-        &mut p ,&mut q, 50, 0., &array![0.01], &array![100], xi, x_max, y_max, 0.0, false, false);
+    let (energy, speeds) = simulation::evolve_system(&mut sim, 50, &StopCondition::never(), &mut []).unwrap();
+    let p = sim.into_particles();
 
     let filename = "save_test";
-    save_data::particles_to_file(&p, filename);
-    save_data::energy_to_file(&energy, filename);
-    save_data::speed_to_file(&speeds, filename);
-    plotting::plot_energy_single_mass(&energy);
+    save_data::particles_to_file(&p, filename).unwrap();
+    save_data::energy_to_file(&energy, &p.species_names, filename).unwrap();
+    save_data::speed_to_file(&speeds, &p.species_names, filename).unwrap();
+
+    // The particles read back must be exactly the ones written.
+    let q = save_data::file_to_particles(filename).unwrap();
+    assert_eq!(q.pos, p.pos);
+    assert_eq!(q.vel, p.vel);
+    assert_eq!(q.r, p.r);
+    assert_eq!(q.m, p.m);
+    assert_eq!(q.collision_count, p.collision_count);
+    assert_eq!(q.species, p.species);
+    assert_eq!(q.species_names, p.species_names);
+
+    // So must the names of the species.
+    let mut s = Scenario::new("save_names_test", array![20, 10], array![0.01, 0.02], array![1., 4.]);
+    s.set_species_names(&["small", "large"]);
+    let named = s.initiate();
+    save_data::particles_to_file(&named, "save_names_test").unwrap();
+    let q = save_data::file_to_particles("save_names_test").unwrap();
+    assert_eq!(q.species, named.species);
+    assert_eq!(q.species_names, vec!["small".to_owned(), "large".to_owned()]);
+
+    // Names that would break the file are refused.
+    let mut badly_named = named.copy();
+    for name in ["a\tb", "a\nb", "\"a\""]
+    {
+        badly_named.species_names[0] = name.to_owned();
+        assert!(save_data::particles_to_file(&badly_named, "save_names_test").is_err());
+    }
+
+    let options = plotting::EnergyPlot { axes: plotting::Axes::LogLog, normalized: true, cooling_fit: true };
+    plotting::plot_energy(&energy, &p.species_names, &options, &plotting::PlotOutput::png(filename, 800, 600));
+    plotting::plot_energy(&energy, &p.species_names, &options, &plotting::PlotOutput::svg(filename, 800, 600));
     //plotting::plot_stats(speeds.slice(s![0,..]), speeds.slice(s![1,..]));
 }
 
+// Runs a small ensemble, and checks that the averaged energy
+// starts at the (identical) initial energy of every realization.
+fn test_ensemble()
+{
+    let mut s = Scenario::new("ensemble_test", array![20], array![0.01], array![1.]);
+    s.number_of_events = 200;
+    // Seeds this large are not exact as f64.
+    s.seed = u64::MAX - 1;
+    let mut ensemble = Ensemble::new("ensemble_test", &["collisions"], 4);
+    ensemble.set_workers(2);
+    let average = ensemble.run(&s, |s, _k|
+    {
+        let (p, energy, speeds) = s.simulate().unwrap();
+        Ok(Realization { energy, speeds, metrics: vec![p.get_avg_collision_count()] })
+    }).expect("The ensemble failed.");
+
+    let e_0 = 20. * 0.5 * parameters::V_0.powi(2);
+    assert!((average.energy[[1, 0]] - e_0).abs() < 1e-9, 
+        "Averaged energy is {}, but {} was expected", average.energy[[1, 0]], e_0);
+    assert!(average.energy[[2, 0]].abs() < 1e-9);
+    assert_eq!(average.energy.nrows(), 5);
+    assert_eq!(average.speeds.nrows(), 5);
+    assert_eq!(average.metrics.dim(), (2, 1));
+
+    // Every realization is recorded with the exact seed it was run with.
+    let path = save_data::get_output_dir().join("ensemble_test_ensemble_realizations.csv");
+    let text = std::fs::read_to_string(&path).expect("Could not read the realizations.");
+    let seeds: Vec<u64> = text.lines().skip(1)
+        .map(|line| line.split('\t').next().unwrap().parse().unwrap()).collect();
+    assert_eq!(seeds, vec![u64::MAX - 1, u64::MAX, 0, 1]);
+}
+
+// Runs the same scenario twice with the same seed, which
+// should give identical trajectories, and once with another.
+fn test_reproducibility()
+{
+    let mut s = Scenario::new("seed_test", array![50, 50], array![0.01, 0.02], array![1., 2.]);
+    s.number_of_events = 500;
+    s.seed = 42;
+    let (p_1, energy_1, _speeds) = s.simulate().unwrap();
+    let (p_2, energy_2, _speeds) = s.simulate().unwrap();
+    assert_eq!(p_1.pos, p_2.pos, "Equal seeds gave different positions.");
+    assert_eq!(p_1.vel, p_2.vel, "Equal seeds gave different velocities.");
+    assert_eq!(energy_1, energy_2, "Equal seeds gave different energies.");
+
+    s.seed = 43;
+    let (p_3, _energy, _speeds) = s.simulate().unwrap();
+    assert!(p_1.pos != p_3.pos, "Different seeds gave equal positions.");
+
+    // The seed is recorded exactly, even above 2^53.
+    s.seed = (1 << 60) + 1;
+    save_data::scenario_to_file(&s, "seed_test").unwrap();
+    let path = save_data::get_output_dir().join("seed_test_scenario.csv");
+    let text = std::fs::read_to_string(&path).expect("Could not read the scenario.");
+    assert!(text.lines().any(|line| line == "seed\t1152921504606846977"));
+}
+
+// Places a dense binary mixture with every placement strategy,
+// and checks that all particles are inside the box without overlaps.
+fn test_placement()
+{
+    let strategies = [
+        particle::Placement::RandomSequential { max_failures: 10000 },
+        particle::Placement::SquareLattice,
+        particle::Placement::HexagonalLattice];
+    for placement in strategies.iter()
+    {
+        println!("Placement: {:?}", placement);
+        let p = particle::generate_particles(
+            &array![900, 100],
+            &array![0.005, 0.01],
+            &array![1., 4.],
+            &particle::GenerationOptions
+            {
+                placement: placement.clone(),
+                ..particle::GenerationOptions::new(2, 1.0, 0.5)
+            },
+            &mut simulation::make_rng(parameters::SEED));
+        for i in 0..p.get_len()
+        {
+            assert!(p.is_within_box(i, parameters::X_MIN, 1.0, parameters::Y_MIN, 0.5),
+                "Particle {} was placed outside the box.", i);
+            assert!(!p.is_overlapping(i), "Particle {} was placed overlapping.", i);
+        }
+    }
+
+    // 1100 disks of radius 0.015 need 34 columns of width 0.03 on a
+    // square lattice, which do not fit in a unit box. They do fit on
+    // a hexagonal lattice.
+    let r = 0.015;
+    assert!(1100f64.sqrt().ceil() * 2.*r > 1.);
+    let p = particle::generate_particles(
+        &array![1100], &array![r], &array![1.],
+        &particle::GenerationOptions
+        {
+            placement: particle::Placement::HexagonalLattice,
+            ..particle::GenerationOptions::new(1, 1., 1.)
+        },
+        &mut simulation::make_rng(parameters::SEED));
+    for i in 0..p.get_len()
+    {
+        assert!(p.is_within_box(i, 0., 1., 0., 1.), "Particle {} was placed outside the box.", i);
+        assert!(!p.is_overlapping(i), "Particle {} was placed overlapping.", i);
+    }
+}
+
+// Compresses a binary mixture to a high packing fraction,
+// and checks that the particles do not overlap afterwards.
+fn test_compression()
+{
+    let n = array![300, 100];
+    let r = array![0.02, 0.028];
+    let pf = particle::get_packing_fraction(&n, &r, 0., 0., 1., 1.);
+    println!("Target packing fraction: {}", pf);
+    let placement = particle::Placement::Compressed { 
+        initial_fraction: 0.3, growth_rate: 0.01, max_events: 10000000 };
+    let p = particle::generate_particles(
+        &n, &r, &array![1., 2.], 
+        &particle::GenerationOptions { placement, ..particle::GenerationOptions::new(2, 1., 1.) },
+        &mut simulation::make_rng(parameters::SEED));
+
+    for i in 0..p.get_len()
+    {
+        assert!(p.is_within_box(i, -1e-9, 1. + 1e-9, -1e-9, 1. + 1e-9),
+            "Particle {} was compressed out of the box.", i);
+        for j in 0..i
+        {
+            let d = ((p.pos[[0, i]] - p.pos[[0, j]]).powi(2) 
+                + (p.pos[[1, i]] - p.pos[[1, j]]).powi(2)).sqrt();
+            assert!(d > (p.r[i] + p.r[j]) * (1. - 1e-9), 
+                "Particles {} and {} overlap after compression.", i, j);
+        }
+    }
+
+    // A compression that runs out of events must say so, and
+    // leave the particles at the size they had reached.
+    let mut p = particle::generate_particles(
+        &n, &(0.5*&r), &array![1., 2.],
+        &particle::GenerationOptions::new(2, 1., 1.),
+        &mut simulation::make_rng(parameters::SEED));
+    let (reached, done) = compression::compress(&mut p, pf, 0.01, 1000, 1., 1.);
+    assert!(!done, "The compression reached {} in 1000 events.", reached);
+    assert!(reached < pf);
+    for i in 0..p.get_len()
+    {
+        assert!(p.r[i] < r[p.species[i]]);
+        for j in 0..i
+        {
+            let d = ((p.pos[[0, i]] - p.pos[[0, j]]).powi(2) 
+                + (p.pos[[1, i]] - p.pos[[1, j]]).powi(2)).sqrt();
+            assert!(d > (p.r[i] + p.r[j]) * (1. - 1e-9), 
+                "Particles {} and {} overlap after an incomplete compression.", i, j);
+        }
+    }
+}
+
+// Draws Maxwell-Boltzmann velocities with one temperature per species,
+// and checks the momentum, the total energy and the temperatures.
+fn test_velocity_distributions()
+{
+    let m = Array1::from_shape_fn(20000, |i| if i < 10000 { 1. } else { 4. });
+    let species = Array1::from_shape_fn(20000, |i| if i < 10000 { 0 } else { 1 });
+    let mut initial = particle::InitialVelocities::maxwell_boltzmann(1.);
+    initial.distribution 
+        = particle::VelocityDistribution::MaxwellBoltzmannPerSpecies(array![1., 3.]);
+    let mut rng = simulation::make_rng(parameters::SEED);
+
+    let vel = particle::generate_velocities(&m, &species, &initial, &mut rng);
+    for k in 0..2
+    {
+        assert!(vel.row(k).dot(&m).abs() < 1e-8, "Total momentum is not zero.");
+    }
+    // In two dimensions, the mean kinetic energy per particle is T.
+    let e: Array1<f64> = (0..20000).map(|i| 0.5 * m[i] 
+        * (vel[[0, i]].powi(2) + vel[[1, i]].powi(2))).collect();
+    let t_0 = e.slice(s![..10000]).mean().unwrap();
+    let t_1 = e.slice(s![10000..]).mean().unwrap();
+    assert!((t_0 - 1.).abs() < 0.05, "Temperature of species 0 is {}", t_0);
+    assert!((t_1 - 3.).abs() < 0.15, "Temperature of species 1 is {}", t_1);
+
+    initial.total_energy = Some(100.);
+    let vel = particle::generate_velocities(&m, &species, &initial, &mut rng);
+    let e: f64 = (0..20000).map(|i| 0.5 * m[i] 
+        * (vel[[0, i]].powi(2) + vel[[1, i]].powi(2))).sum();
+    assert!((e - 100.).abs() < 1e-9, "Total energy is {}", e);
+}
+
+// Generates particles with continuous size distributions, and checks
+// the masses and that the size bins account for every particle.
+fn test_polydisperse()
+{
+    let sizes = [
+        particle::SizeDistribution::Uniform { min: 0.002, max: 0.004, density: 10. },
+        particle::SizeDistribution::LogNormal { mu: (0.003f64).ln(), sigma: 0.2, density: 20. }];
+    let p = particle::generate_particles(
+        &array![300, 300], &array![0., 0.], &array![0., 0.],
+        &particle::GenerationOptions
+        {
+            sizes: sizes.to_vec(),
+            placement: particle::Placement::RandomSequential { max_failures: 10000 },
+            ..particle::GenerationOptions::new(2, 1., 1.)
+        },
+        &mut simulation::make_rng(parameters::SEED));
+
+    for i in 0..p.get_len()
+    {
+        let density = if i < 300 { 10. } else { 20. };
+        let m = density * std::f64::consts::PI * p.r[i].powi(2);
+        assert!((p.m[i] - m).abs() < 1e-12, "Particle {} has the wrong mass.", i);
+    }
+    assert!(p.r.slice(s![..300]).iter().all(|r| (0.002..0.004).contains(r)));
+
+    let edges = p.get_size_bins(10);
+    assert_eq!(p.get_count_per_size_bin(&edges).sum(), 600);
+    let e = p.get_kinetic_energy_per_size_bin(&edges).sum();
+    assert!((e - p.get_tot_kinetic_energy()).abs() < 1e-9);
+}
+
+// Two species with equal mass but different radius must
+// be kept apart in the energy and speed statistics.
+fn test_species()
+{
+    let mut s = Scenario::new("species_test", array![100, 50], array![0.005, 0.01], array![1., 1.]);
+    s.set_species_names(&["small", "large"]);
+    s.number_of_events = 200;
+    let (p, energy, speeds) = s.simulate().unwrap();
+
+    assert_eq!(p.get_species_count(), 2);
+    assert_eq!(p.get_species_name(120), "large");
+    assert_eq!(energy.nrows(), 4);
+    let e = energy.column(energy.ncols() - 1);
+    assert!((e[1] - e[2] - e[3]).abs() < 1e-9, "The species energies do not add up.");
+    for (k, n_k) in s.n.iter().enumerate()
+    {
+        let count = speeds.row(k + 1).iter().filter(|v| v.is_finite()).count();
+        assert_eq!(count, *n_k, "Species {} has the wrong number of speeds.", k);
+    }
+}
+
+// Writes a short trajectory in both formats, and checks
+// that every frame has been written in full.
+fn test_trajectory()
+{
+    let mut s = Scenario::new("trajectory_test", array![20, 10], array![0.01, 0.02], array![1., 2.]);
+    s.set_species_names(&["small", "large"]);
+    s.number_of_events = 100;
+
+    for (format, lines_per_frame) in [
+        (trajectory::TrajectoryFormat::ExtendedXyz, 2 + 30),
+        (trajectory::TrajectoryFormat::LammpsDump, 11 + 30)]
+    {
+        s.trajectory = Some((format, 10));
+        s.simulate().unwrap();
+        let path = save_data::data_path(&s.name, &format!(".{}", format.extension())).unwrap();
+        let text = std::fs::read_to_string(&path).expect("Could not read trajectory.");
+        assert_eq!(text.lines().count(), 11 * lines_per_frame,
+            "The {:?} trajectory has the wrong length.", format);
+    }
+}
+
+// Logs every collision of an inelastic run. The logged energy
+// losses must add up to the energy lost by the system.
+fn test_event_log()
+{
+    let mut s = Scenario::new("event_log_test", array![50, 50], array![0.01, 0.02], array![1., 4.]);
+    s.xi = 0.8;
+    s.number_of_events = 200;
+    s.event_log = Some(event_log::EventLogFormat::Binary);
+    let (_p, energy, _speeds) = s.simulate().unwrap();
+
+    let events = event_log::read_binary_event_log(&s.name).expect("Could not read event log.");
+    assert_eq!(events.len(), 200);
+    let mut t = 0.;
+    for c in events.iter()
+    {
+        assert!(c.time >= t, "The events are not in order.");
+        assert!(c.normal_velocity_before <= 0., "An event was logged for receding particles.");
+        assert!((c.normal_velocity_after + s.xi * c.normal_velocity_before).abs() < 1e-12);
+        t = c.time;
+    }
+    // Row 1 of energy holds the energy before each event.
+    let lost: f64 = events.iter().take(199).map(|c| c.energy_loss).sum();
+    assert!((lost - (energy[[1, 0]] - energy[[1, 199]])).abs() < 1e-9,
+        "The logged energy loss does not match the energy of the system.");
+
+    s.event_log = Some(event_log::EventLogFormat::Csv);
+    s.simulate().unwrap();
+    let path = save_data::data_path(&s.name, event_log::EventLogFormat::Csv.suffix()).unwrap();
+    let text = std::fs::read_to_string(&path).expect("Could not read event log.");
+    assert_eq!(text.lines().count(), 201);
+}
+
+// Run directories with the same name must not overwrite each other.
+fn test_run_dirs()
+{
+    let first = save_data::create_run_dir("run_dir_test").unwrap();
+    let second = save_data::create_run_dir("run_dir_test").unwrap();
+    assert!(first != second, "Two runs got the same directory.");
+    for dir in [first, second]
+    {
+        let path = save_data::get_output_dir().join(dir);
+        assert!(path.is_dir());
+        std::fs::remove_dir(path).unwrap();
+    }
+}
+
+// Checks the points of a sweep and the directory each is run in,
+// and that running the points in parallel changes nothing.
+fn test_sweep()
+{
+    let mut sweep = Sweep::new("sweep_test", &["energy", "collisions"]);
+    sweep.add_values("xi", &[0.5, 0.9]);
+    sweep.add_linspace("v_0", 1., 2., 3);
+    assert_eq!(sweep.get_len(), 6);
+    // The last parameter varies fastest.
+    assert_eq!(sweep.get_points(), vec![
+        vec![0.5, 1.], vec![0.5, 1.5], vec![0.5, 2.],
+        vec![0.9, 1.], vec![0.9, 1.5], vec![0.9, 2.]]);
+
+    let mut base = Scenario::new("sweep_test", array![30], array![0.02], array![1.]);
+    base.number_of_events = 300;
+    let mut runs = Vec::new();
+    for workers in [1, 3]
+    {
+        sweep.set_workers(workers);
+        let names = std::sync::Mutex::new(Vec::new());
+        let summary = sweep.run(&base, |s, filename|
+        {
+            names.lock().unwrap().push(filename.to_owned());
+            let (p, energy, _speeds) = s.simulate()?;
+            Ok(vec![energy[[1, energy.ncols() - 1]], p.get_avg_collision_count()])
+        }).unwrap();
+        let mut names = names.into_inner().unwrap();
+        names.sort();
+        runs.push((summary, names));
+    }
+
+    let (serial, serial_names) = &runs[0];
+    let (parallel, parallel_names) = &runs[1];
+    assert_eq!(serial.dim(), (4, 6));
+    assert_eq!(serial, parallel, "The summary depends on the number of workers.");
+    for k in 0..6
+    {
+        assert_eq!(serial.slice(s![..2, k]).to_vec(), sweep.get_points()[k]);
+    }
+
+    // Every run gets its own directory, with one prefix per point.
+    let dir = |names: &Vec<String>| names[0].split('/').next().unwrap().to_owned();
+    let (first, second) = (dir(serial_names), dir(parallel_names));
+    let read_summary = |dir: &str| std::fs::read_to_string(save_data::get_output_dir()
+        .join(format!("{}/sweep_test_summary.csv", dir))).expect("Could not read the summary.");
+    assert_eq!(read_summary(&first), read_summary(&second));
+    assert!(first.starts_with("sweep_test") && second.starts_with("sweep_test"));
+    assert!(first != second, "Two sweeps were run in the same directory.");
+    for (dir, names) in [(&first, serial_names), (&second, parallel_names)]
+    {
+        let expected: Vec<String> = (0..6).map(|k| format!("{}/point_{:03}", dir, k)).collect();
+        assert_eq!(names, &expected);
+        for name in names.iter()
+        {
+            assert!(save_data::get_output_dir().join(name.to_owned() + "_scenario.csv").is_file());
+        }
+        std::fs::remove_dir_all(save_data::get_output_dir().join(dir)).unwrap();
+    }
+}
+
+// Renders a snapshot and a short animation, and checks
+// that complete PNG, SVG and GIF files were written.
+fn test_render()
+{
+    let mut s = Scenario::new("render_test", array![100, 5], array![0.01, 0.05], array![1., 10.]);
+    s.x_max = 2.0;
+    let p = s.initiate();
+    render::render_png(&p, s.x_max, s.y_max, render::ColorBy::Species, 400, &s.name).unwrap();
+    render::render_svg(&p, s.x_max, s.y_max, render::ColorBy::Speed(2.), 400, &s.name).unwrap();
+
+    for format in [render::AnimationFormat::Gif(5), render::AnimationFormat::PngFrames]
+    {
+        let mut animation = render::Animation::new(
+            &s.name, format, 10, 200, render::ColorBy::CollisionCount(5));
+        let mut sim = simulation::Simulation::new(
+            p.copy(), s.x_max, s.y_max, simulation::CollisionModel::new(s.xi, false), 0.);
+        simulation::evolve_system(&mut sim, 50, &StopCondition::never(), &mut [&mut animation]).unwrap();
+    }
+
+    let read = |suffix: &str| std::fs::read(save_data::data_path(&s.name, suffix).unwrap()).unwrap();
+    assert_eq!(&read(".png")[..8], b"\x89PNG\r\n\x1a\n");
+    assert!(String::from_utf8(read(".svg")).unwrap().trim_end().ends_with("</svg>"));
+    let gif = read(".gif");
+    assert_eq!(&gif[..6], b"GIF89a");
+    assert_eq!(gif.last(), Some(&0x3b), "The GIF has no trailer.");
+    assert_eq!(&read("_frame_00005.png")[..4], b"\x89PNG");
+}
+
+// Fitting Haff's law to an exact cooling curve must give back
+// its time scale, and an elastic gas must not cool at all.
+fn test_haff_fit()
+{
+    let t = Array::linspace(1., 11., 100);
+    let e = t.mapv(|time: f64| 50. / (1. + (time - 1.) / 2.5).powi(2));
+    let tau = plotting::fit_haff_law(t.view(), e.view());
+    assert!((tau - 2.5).abs() < 1e-9, "Fitted tau = {}, and 2.5 was expected", tau);
+    assert_eq!(plotting::fit_haff_law(t.view(), Array1::ones(100).view()), f64::INFINITY);
+}
+
+// Draws the dashboard of a two-species system in both styles.
+fn test_dashboard()
+{
+    let mut s = Scenario::new("dashboard_test", array![200, 20], array![0.005, 0.02], array![1., 4.]);
+    s.set_species_names(&["small", "large"]);
+    s.x_max = 2.0;
+    let p = s.initiate();
+
+    let mut view = dashboard::Dashboard::new(1, 20, dashboard::MapStyle::Blocks);
+    let text = view.draw(&p, 1234, 0, 0., s.x_max, s.y_max);
+    println!("{}", text);
+    // The map has 5 rows and a frame, then come events, energy and two temperatures.
+    assert_eq!(text.lines().count(), 5 + 2 + 2 + 2);
+    assert!(text.contains("queue = 1234"));
+    assert!(text.lines().last().unwrap().starts_with("T large"));
+    assert!(text.lines().nth(1).unwrap().chars().any(|c| c != ' ' && c != '|'));
+
+    let mut view = dashboard::Dashboard::new(1, 20, dashboard::MapStyle::Braille);
+    let text = view.draw(&p, 0, 0, 0., s.x_max, s.y_max);
+    println!("{}", text);
+    assert!(text.chars().any(|c| ('\u{2801}'..='\u{28ff}').contains(&c)));
+}
+
+// Stepping through a simulation by hand must give the same
+// system as evolve_system, and run_until must stop exactly
+// at the given time without resolving later collisions.
+fn test_stepping()
+{
+    let mut s = Scenario::new("stepping_test", array![100], array![0.01], array![1.]);
+    s.xi = 0.9;
+    s.tc = true;
+    let events = 300;
+
+    let mut sim = s.build();
+    let (energy, _speeds) = simulation::evolve_system(&mut sim, events, &StopCondition::never(), &mut []).unwrap();
+
+    let mut stepped = s.build();
+    for i in 0..events
+    {
+        assert_eq!(stepped.get_time(), energy[[0, i]]);
+        let outcome = stepped.step().unwrap();
+        assert_eq!(outcome.time, stepped.get_time());
+        assert!(outcome.normal_velocity_before <= 0.);
+    }
+    assert_eq!(stepped.get_event_count(), events);
+    assert_eq!(stepped.get_tc_event_count(), sim.get_tc_event_count());
+    stepped.sync();
+    assert_eq!(stepped.get_particles().pos, sim.get_particles().pos);
+    assert_eq!(stepped.get_particles().vel, sim.get_particles().vel);
+
+    let t_end = stepped.get_time() + 0.05;
+    let next = stepped.get_next_time().unwrap();
+    let resolved = stepped.run_until(t_end);
+    assert_eq!(stepped.get_time(), t_end);
+    assert_eq!(stepped.get_event_count(), events + resolved);
+    assert!(next > t_end || resolved > 0);
+    assert!(stepped.get_next_time().unwrap() > t_end);
+    assert_eq!(stepped.run_events(10), 10);
+
+    // Stopping with run_until just before an event must not make
+    // the TC model take it as coming right after the previous one.
+    let mut s = Scenario::new("tc_stepping_test", array![400], array![0.015], array![1.]);
+    s.xi = 0.5;
+    s.tc = true;
+    let mut sim = s.build();
+    let mut stepped = s.build();
+    for _ in 0..1000
+    {
+        let next = stepped.get_next_time().unwrap();
+        if next - stepped.get_time() > parameters::TC_DT
+        {
+            assert_eq!(stepped.run_until(next - 0.5*parameters::TC_DT), 0);
+        }
+        stepped.step();
+        sim.step();
+        assert_eq!(stepped.get_tc_event_count(), sim.get_tc_event_count());
+    }
+    assert!(sim.get_tc_event_count() > 0);
+    stepped.sync();
+    sim.sync();
+    assert_eq!(stepped.get_particles().vel, sim.get_particles().vel);
+}
+
+// Counts what an observer is told during a run.
+struct CountingObserver
+{
+    events: usize,
+    samples: Vec<usize>,
+    finished: Option<usize>,
+}
+
+impl Observer for CountingObserver
+{
+    fn get_sample_interval(&self) -> usize
+    {
+        25
+    }
+
+    fn on_event(&mut self, _sim: &simulation::Simulation, event: usize,
+        outcome: &granular_gas::CollisionOutcome) -> save_data::SaveResult<()>
+    {
+        self.events += 1;
+        assert_eq!(event, self.events);
+        assert!(outcome.energy_loss >= 0.);
+        Ok(())
+    }
+
+    fn on_sample(&mut self, _sim: &simulation::Simulation, event: usize) -> save_data::SaveResult<()>
+    {
+        self.samples.push(event);
+        Ok(())
+    }
+
+    fn on_finish(&mut self, _sim: &simulation::Simulation, events: usize) -> save_data::SaveResult<()>
+    {
+        self.finished = Some(events);
+        Ok(())
+    }
+}
+
+// A user defined observer must be called on every event, on
+// every sample including the initial state, and at the end.
+fn test_observer()
+{
+    let mut s = Scenario::new("observer_test", array![50], array![0.01], array![1.]);
+    s.xi = 0.9;
+    let mut counter = CountingObserver { events: 0, samples: Vec::new(), finished: None };
+    let mut sim = s.build();
+    let (energy, speeds) = simulation::evolve_system(
+        &mut sim, 100, &StopCondition::never(), &mut [&mut counter]).unwrap();
+    assert_eq!(counter.events, 100);
+    assert_eq!(counter.samples, vec![0, 25, 50, 75, 100]);
+    assert_eq!(counter.finished, Some(100));
+    // The energy is recorded before the first and after every event.
+    assert_eq!(energy.dim(), (3, 101));
+    assert_eq!(energy[[0, 100]], sim.get_time());
+    assert!(speeds.row(1).iter().all(|v| v.is_finite()));
+}
+
+// Every kind of stop condition must end a run as soon as it
+// is met, and not before.
+fn test_stop_conditions()
+{
+    // A condition can be met between checking it and showing its
+    // progress, so the status bar must take a full bar, or more.
+    simulation::status_bar(1000, 1000);
+    simulation::status_bar(1001, 1000);
+
+    let mut s = Scenario::new("stop_test", array![100], array![0.01], array![1.]);
+    s.xi = 0.8;
+    let run = |stop: StopCondition|
+    {
+        let mut sim = s.build();
+        let events = simulation::run(&mut sim, &stop, &mut []).unwrap();
+        (sim, events)
+    };
+
+    let (sim, events) = run(StopCondition::Events(123));
+    assert_eq!(events, 123);
+    assert_eq!(sim.get_event_count(), 123);
+
+    let (sim, _) = run(StopCondition::Time(0.2));
+    assert!(sim.get_time() >= 0.2);
+
+    let (sim, events) = run(StopCondition::CollisionsPerParticle(3.));
+    let p = sim.get_particles();
+    assert!(p.get_avg_collision_count() >= 3.);
+    assert_eq!(p.get_avg_collision_count(), p.collision_count.iter().sum::<u32>() as f64 / 100.);
+    assert_eq!(sim.get_avg_collision_count(), p.get_avg_collision_count());
+    assert!(events <= 300, "{} events for 3 collisions per particle.", events);
+
+    let e_0 = s.initiate().get_tot_kinetic_energy();
+    let (sim, _) = run(StopCondition::EnergyFraction(0.5));
+    assert!(sim.get_particles().get_tot_kinetic_energy() <= 0.5*e_0);
+
+    let (_, events) = run(StopCondition::WallClock(std::time::Duration::from_secs(0)));
+    assert_eq!(events, 0);
+
+    let (sim, _) = run(StopCondition::predicate(|sim| sim.get_particles().collision_count[0] > 0));
+    assert_eq!(sim.get_particles().collision_count[0], 1);
+
+    // Any stops at the first condition, All at the last.
+    let (_, events) = run(StopCondition::Events(50).or(StopCondition::Events(80)));
+    assert_eq!(events, 50);
+    let (_, events) = run(StopCondition::Events(50).and(StopCondition::Events(80)));
+    assert_eq!(events, 80);
+}
+
+// Long runs must be recorded in bounded memory, runs that stop
+// early must give only the recorded samples, and the streamed
+// energy must match the one recorded in memory.
+fn test_recording()
+{
+    let mut s = Scenario::new("recording_test", array![100], array![0.01], array![1.]);
+    s.xi = 0.8;
+
+    let mut sim = s.build();
+    let mut decimated = observer::EnergyRecorder::with_limit(1, 64);
+    let mut every = observer::EnergyRecorder::new(1);
+    let events = simulation::run(
+        &mut sim, &StopCondition::Events(1000), &mut [&mut decimated, &mut every]).unwrap();
+    let (decimated, every) = (decimated.into_data(), every.into_data());
+    assert_eq!(every.ncols(), events + 1);
+    assert!(decimated.ncols() <= 64 + 1, "{} samples were kept.", decimated.ncols());
+    assert_eq!(decimated.column(0), every.column(0));
+    assert_eq!(decimated.column(decimated.ncols() - 1), every.column(events));
+    // 1000 events fit in 64 samples at an interval of 16.
+    assert_eq!(decimated.column(1), every.column(16));
+
+    let mut sim = s.build();
+    let (energy, _speeds) = simulation::evolve_system(
+        &mut sim, 100000, &StopCondition::EnergyFraction(0.5), &mut []).unwrap();
+    assert_eq!(energy.ncols(), sim.get_event_count() + 1);
+    assert!(energy[[1, energy.ncols() - 1]] <= 0.5 * energy[[1, 0]]);
+
+    s.energy_stream = Some(10);
+    s.number_of_events = 95;
+    let (_p, energy, _speeds) = s.simulate().unwrap();
+    let path = save_data::data_path(&s.name, "_energy.csv").unwrap();
+    let text = std::fs::read_to_string(&path).expect("Could not read energy.");
+    let lines: Vec<&str> = text.lines().collect();
+    // A header, events 0, 10, ..., 90, and the last event.
+    assert_eq!(lines.len(), 1 + 10 + 1);
+    let last: Vec<f64> = lines[11].split_whitespace().map(|v| v.parse().unwrap()).collect();
+    assert_eq!(last, energy.column(95).to_vec());
+}
+
+// The energy kept up to date from the colliding particles must
+// agree with the energy summed over all particles, for every
+// species, through an inelastic run and its periodic checks.
+fn test_energy_bookkeeping()
+{
+    let mut s = Scenario::new("energy_test", array![200, 50], array![0.005, 0.01], array![1., 4.]);
+    s.xi = 0.7;
+    s.tc = true;
+    let mut sim = s.build();
+    // Rounding errors are relative to the energy when they were made.
+    let e_0 = sim.get_kinetic_energy();
+    for _ in 0..25
+    {
+        sim.run_events(1000);
+        let p = sim.get_particles();
+        for k in 0..2
+        {
+            let exact = p.get_kinetic_energy_for_species(k);
+            assert!((sim.get_kinetic_energy_for_species(k) - exact).abs() <= 1e-10 * e_0,
+                "Species {} has energy {}, but {} was kept.", k, exact, sim.get_kinetic_energy_for_species(k));
+        }
+    }
+    // The run passed two periodic checks.
+    assert!(sim.get_max_energy_drift() < 1e-12);
+    assert!(sim.check_energy() < 1e-12);
+    let exact = sim.get_particles().get_tot_kinetic_energy();
+    assert!((sim.get_kinetic_energy() - exact).abs() <= 1e-12 * exact);
+
+    // Particles at rest have no energy to measure a drift against.
+    s.velocities = particle::InitialVelocities::fixed(0.);
+    let mut sim = s.build();
+    assert_eq!(sim.check_energy(), 0.);
+    assert_eq!(sim.get_max_energy_drift(), 0.);
+}
+
+// Moving only the colliding particles must give the same events,
+// and after a sync the same positions, as moving all particles to
+// every event. Rounding differs between the two, and grows with
+// every collision, so positions are only compared approximately.
+fn test_lazy_positions()
+{
+    let s = Scenario::new("lazy_test", array![100, 20], array![0.01, 0.02], array![1., 2.]);
+    let mut lazy = s.build();
+    let mut eager = s.build();
+    for _ in 0..200
+    {
+        let a = lazy.step().unwrap();
+        let b = eager.step().unwrap();
+        eager.sync();
+        assert_eq!((a.particle_1, a.particle_2), (b.particle_1, b.particle_2));
+        assert!((a.time - b.time).abs() < 1e-9);
+    }
+    let t = lazy.get_time();
+    let behind = lazy.get_particles().sync_time.iter().filter(|time| **time < t).count();
+    assert!(behind > 60, "Only {} particles were left behind.", behind);
+
+    lazy.sync();
+    assert!(lazy.get_particles().sync_time.iter().all(|time| *time == t));
+    let error = (&lazy.get_particles().pos - &eager.get_particles().pos).mapv(f64::abs)
+        .fold(0., |a: f64, e| a.max(*e));
+    println!("Largest difference between lazy and eager positions: {:e}", error);
+    assert!(error < 1e-9);
+
+    // run_until only moves the time, until the particles are synced.
+    lazy.run_until(t + 0.01);
+    let (x, y) = lazy.get_particles().get_position_at(0, t + 0.01);
+    let p = lazy.into_particles();
+    assert_eq!((p.pos[[0, 0]], p.pos[[1, 0]]), (x, y));
+}
+
+// Both queues must resolve the same events, and the indexed one
+// must hold at most one per particle. Also times both queues.
+fn test_indexed_queue()
+{
+    let mut s = Scenario::new("queue_test", array![1000, 200], array![0.004, 0.008], array![1., 2.]);
+    s.xi = 0.9;
+    let mut heap = s.build();
+    s.queue = QueueKind::Indexed;
+    let mut indexed = s.build();
+    let n = indexed.get_particles().get_len();
+    for _ in 0..2000
+    {
+        let a = heap.step().unwrap();
+        let b = indexed.step().unwrap();
+        // A collision of two particles may be found from either one.
+        let pair = |i: i32, j: i32| (i.min(j), i.max(j));
+        assert_eq!(pair(a.particle_1, a.particle_2), pair(b.particle_1, b.particle_2));
+        assert!((a.time - b.time).abs() < 1e-12);
+        assert!(indexed.get_queue().get_len() <= n);
+    }
+    heap.sync();
+    indexed.sync();
+    let error = (&heap.get_particles().pos - &indexed.get_particles().pos).mapv(f64::abs)
+        .fold(0., |a: f64, e| a.max(*e));
+    assert!(error < 1e-9, "The positions differ by {:e}.", error);
+    println!("Queue lengths after 2000 events: heap {}, indexed {}",
+        heap.get_queue().get_len(), indexed.get_queue().get_len());
+
+    for kind in [QueueKind::Heap, QueueKind::Indexed]
+    {
+        s.queue = kind;
+        let start = Instant::now();
+        let mut sim = s.build();
+        sim.run_events(20000);
+        println!("{:?} queue: 20000 events in {:.2} s, {} entries left",
+            kind, start.elapsed().as_secs_f64(), sim.get_queue().get_len());
+    }
+}
+
+// Collisions found on several threads must give exactly the same
+// events as collisions found on one, with both kinds of queue.
+// Also times filling the queue and stepping on several threads.
+fn test_parallel_queue()
+{
+    let n = parameters::PARALLEL_PAIR_THRESHOLD + 200;
+    let mut s = Scenario::new("parallel_queue_test", array![n], array![0.003], array![1.]);
+    s.xi = 0.9;
+    for kind in [QueueKind::Heap, QueueKind::Indexed]
+    {
+        s.queue = kind;
+        let mut sims = Vec::new();
+        for workers in [1, 4]
+        {
+            parallel::set_workers(workers);
+            let mut sim = s.build();
+            let events: Vec<_> = (0..300).map(|_| sim.step().unwrap()).collect();
+            sim.sync();
+            sims.push((events, sim.into_particles()));
+        }
+        parallel::set_workers(0);
+
+        let (serial_events, serial) = &sims[0];
+        let (parallel_events, parallel) = &sims[1];
+        for (a, b) in serial_events.iter().zip(parallel_events.iter())
+        {
+            assert_eq!((a.particle_1, a.particle_2, a.time), (b.particle_1, b.particle_2, b.time));
+        }
+        assert_eq!(serial.pos, parallel.pos, "Threads changed the positions.");
+        assert_eq!(serial.vel, parallel.vel, "Threads changed the velocities.");
+    }
+
+    let mut s = Scenario::new("parallel_timing_test", array![4000], array![0.002], array![1.]);
+    s.xi = 0.9;
+    let p = s.initiate();
+    let workers = parallel::available_workers().max(2);
+    let mut times = Vec::new();
+    for w in [1, workers]
+    {
+        parallel::set_workers(w);
+        let start = Instant::now();
+        let mut sim = simulation::Simulation::new(
+            p.copy(), s.x_max, s.y_max, simulation::CollisionModel::new(s.xi, false), 0.);
+        let filled = start.elapsed().as_secs_f64();
+        sim.run_events(2000);
+        let stepped = start.elapsed().as_secs_f64() - filled;
+        println!("\nOn {} threads, filled the queue of {} particles in {:.2} s, \
+            and resolved 2000 events in {:.2} s", w, p.get_len(), filled, stepped);
+        times.push((filled, stepped));
+    }
+    parallel::set_workers(0);
+    println!("Speedup on {} threads: {:.2} filling, {:.2} stepping, with {} threads available",
+        workers, times[0].0 / times[1].0, times[0].1 / times[1].1, parallel::available_workers());
+}
+
 // Asserts that the collision function works properly
 // on one special case.
 fn assert_correct_impact_stats()
@@ -0,0 +1,167 @@
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::PathBuf;
+
+use crate::particle;
+use crate::observer::Observer;
+use crate::simulation::Simulation;
+use crate::save_data;
+
+
+// Text formats that OVITO and similar tools can read.
+// The simulation is two-dimensional, so z is always zero.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum TrajectoryFormat
+{
+    ExtendedXyz,    // <filename>.xyz
+    LammpsDump,     // <filename>.lammpstrj
+}
+
+
+impl TrajectoryFormat
+{
+    pub fn extension(&self) -> &'static str
+    {
+        match self
+        {
+            TrajectoryFormat::ExtendedXyz => "xyz",
+            TrajectoryFormat::LammpsDump => "lammpstrj",
+        }
+    }
+}
+
+
+// Something that records snapshots of the particles during a
+// run, like a trajectory file or an animation. Every FrameWriter
+// is an Observer that writes a frame at every sample.
+pub trait FrameWriter
+{
+    // Number of events between frames.
+    fn get_interval(&self) -> usize;
+
+    fn write_frame(&mut self, p: &particle::Particles, event: usize, t: f64, x_max: f64, y_max: f64)
+        -> save_data::SaveResult<()>;
+
+    fn finish(&mut self) -> save_data::SaveResult<()>;
+}
+
+
+impl<W: FrameWriter> Observer for W
+{
+    fn get_sample_interval(&self) -> usize
+    {
+        self.get_interval()
+    }
+
+    fn on_sample(&mut self, sim: &Simulation, event: usize) -> save_data::SaveResult<()>
+    {
+        let (x_max, y_max) = sim.get_box();
+        self.write_frame(sim.get_particles(), event, sim.get_time(), x_max, y_max)
+    }
+
+    fn on_finish(&mut self, _sim: &Simulation, _events: usize) -> save_data::SaveResult<()>
+    {
+        self.finish()
+    }
+}
+
+
+// Appends a frame of every particle to a trajectory file
+// every `every` events. Frames hold the time, the box,
+// and the id, species, position, velocity and radius of
+// each particle.
+pub struct TrajectoryWriter
+{
+    f: BufWriter<File>,
+    format: TrajectoryFormat,
+    every: usize,
+    frames: usize,
+    path: PathBuf,
+}
+
+
+impl TrajectoryWriter
+{
+    pub fn new(filename: &str, format: TrajectoryFormat, every: usize)
+        -> save_data::SaveResult<TrajectoryWriter>
+    {
+        assert!(every > 0, "Frames must be written at least every event.");
+        let path = save_data::data_path(filename, &format!(".{}", format.extension()))?;
+        let f = File::create(&path)?;
+        Ok(TrajectoryWriter
+        {
+            f: BufWriter::new(f),
+            format,
+            every,
+            frames: 0,
+            path,
+        })
+    }
+
+    fn write_xyz_frame(&mut self, p: &particle::Particles, event: usize, t: f64, x_max: f64, y_max: f64)
+        -> save_data::SaveResult<()>
+    {
+        let f = &mut self.f;
+        writeln!(f, "{}", p.get_len())?;
+        writeln!(f, "Lattice=\"{:?} 0.0 0.0 0.0 {:?} 0.0 0.0 0.0 1.0\" Origin=\"0.0 0.0 -0.5\" \
+            pbc=\"F F F\" Properties=id:I:1:species:S:1:pos:R:3:velo:R:3:radius:R:1 \
+            Time={:?} Event={}", x_max, y_max, t, event)?;
+        for i in 0..p.get_len()
+        {
+            writeln!(f, "{}\t{}\t{:?}\t{:?}\t0.0\t{:?}\t{:?}\t0.0\t{:?}",
+                i, p.get_species_name(i), p.pos[[0, i]], p.pos[[1, i]],
+                p.vel[[0, i]], p.vel[[1, i]], p.r[i])?;
+        }
+        Ok(())
+    }
+
+    // LAMMPS ids and types start at 1, so particle i has
+    // id i + 1, and species k has type k + 1.
+    fn write_lammps_frame(&mut self, p: &particle::Particles, event: usize, t: f64, x_max: f64, y_max: f64)
+        -> save_data::SaveResult<()>
+    {
+        let f = &mut self.f;
+        writeln!(f, "ITEM: TIMESTEP\n{}", event)?;
+        writeln!(f, "ITEM: TIME\n{:?}", t)?;
+        writeln!(f, "ITEM: NUMBER OF ATOMS\n{}", p.get_len())?;
+        writeln!(f, "ITEM: BOX BOUNDS ff ff ff")?;
+        writeln!(f, "0.0 {:?}\n0.0 {:?}\n-0.5 0.5", x_max, y_max)?;
+        writeln!(f, "ITEM: ATOMS id type x y z vx vy vz radius")?;
+        for i in 0..p.get_len()
+        {
+            writeln!(f, "{} {} {:?} {:?} 0.0 {:?} {:?} 0.0 {:?}",
+                i + 1, p.species[i] + 1, p.pos[[0, i]], p.pos[[1, i]],
+                p.vel[[0, i]], p.vel[[1, i]], p.r[i])?;
+        }
+        Ok(())
+    }
+}
+
+
+impl FrameWriter for TrajectoryWriter
+{
+    fn get_interval(&self) -> usize
+    {
+        self.every
+    }
+
+    fn write_frame(&mut self, p: &particle::Particles, event: usize, t: f64, x_max: f64, y_max: f64)
+        -> save_data::SaveResult<()>
+    {
+        match self.format
+        {
+            TrajectoryFormat::ExtendedXyz => self.write_xyz_frame(p, event, t, x_max, y_max)?,
+            TrajectoryFormat::LammpsDump => self.write_lammps_frame(p, event, t, x_max, y_max)?,
+        }
+        self.frames += 1;
+        Ok(())
+    }
+
+    // Flushes the file, and reports where it was written.
+    fn finish(&mut self) -> save_data::SaveResult<()>
+    {
+        self.f.flush()?;
+        println!("{} trajectory frames saved succesfully to file:\n{}", self.frames, self.path.display());
+        Ok(())
+    }
+}